@@ -81,4 +81,4 @@ FROM ctgov.result_groups rg
 WHERE m.rg_id IS NULL
   AND rg.ctgov_group_code LIKE 'E%'
   AND re.subjects_affected > 0
-  AND lower(rg.title) NOT IN ('total', 'all@patients', 'overall participants', 'all participants');
\ No newline at end of file
+  AND lower(rg.title) != ALL ($1);
\ No newline at end of file
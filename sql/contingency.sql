@@ -0,0 +1,46 @@
+-- name: drop_arm_counts
+DROP TABLE IF EXISTS ctgov.arm_event_counts;
+
+-- name: create_arm_counts
+-- The subjects affected and at risk per arm and preferred term. An event reported both as serious and as other, or
+-- under several terms mapping to the same preferred term, may count the same subjects twice, so the largest count is
+-- taken instead of the sum. Results reporting more subjects affected than at risk are capped at the subjects at risk.
+CREATE TABLE ctgov.arm_event_counts AS
+SELECT re.nct_id,
+       re.result_group_id,
+       p.pt_code,
+       max(least(re.subjects_affected, re.subjects_at_risk)) AS subjects_affected,
+       max(re.subjects_at_risk)                              AS subjects_at_risk,
+       bool_or(re.event_type = 'serious')                    AS serious,
+       count(*)                                              AS reported_events
+FROM ctgov.reported_event_pt p
+         JOIN ctgov.reported_events_meddra re ON re.id = p.reported_event_id
+WHERE re.subjects_at_risk > 0
+GROUP BY re.nct_id, re.result_group_id, p.pt_code;
+
+-- name: drop_ingredient_counts
+DROP TABLE IF EXISTS ctgov.ingredient_event_counts;
+
+-- name: create_ingredient_counts
+-- The arm counts for every ingredient given in the arm
+CREATE TABLE ctgov.ingredient_event_counts AS
+WITH ingredients AS (SELECT DISTINCT nct_id, id AS result_group_id, in_rxcui, in_str
+                     FROM ctgov.result_group_ingredient)
+SELECT c.nct_id,
+       c.result_group_id,
+       i.in_rxcui,
+       i.in_str,
+       c.pt_code,
+       h.pt_name,
+       h.soc_name,
+       c.subjects_affected,
+       c.subjects_at_risk,
+       c.serious,
+       c.reported_events
+FROM ctgov.arm_event_counts c
+         JOIN ingredients i ON i.result_group_id = c.result_group_id
+         JOIN meddra.mdhier h ON h.pt_code = c.pt_code AND h.primary_soc_fg = 'Y';
+
+-- name: ingredient_counts_index
+CREATE INDEX ingredient_event_counts_index
+    ON ctgov.ingredient_event_counts (in_rxcui, pt_code);
@@ -1,6 +1,6 @@
-DROP TABLE IF EXISTS ctgov.reported_events_2;
+DROP TABLE IF EXISTS ctgov.reported_events_meddra;
 
-CREATE TABLE ctgov.reported_events_2 AS
+CREATE TABLE ctgov.reported_events_meddra AS
 SELECT DISTINCT re.id,
                 re.nct_id,
                 re.result_group_id,
@@ -13,55 +13,44 @@ SELECT DISTINCT re.id,
                 re.subjects_at_risk,
                 re.description,
                 re.event_count,
-                re.organ_system,
+                CASE
+                    WHEN re.organ_system = 'General disorders'
+                        THEN 'General disorders and administration site conditions'
+                    ELSE re.organ_system
+                    END                                          AS organ_system,
                 re.adverse_event_term,
+                lower(re.adverse_event_term)                     AS term,
+                cast(NULL AS SMALLINT)                           AS ctcae_grade,
                 re.frequency_threshold,
                 re.vocab,
+                CASE
+                    WHEN coalesce(nullif(re.vocab, ''), re.default_vocab) ILIKE '%meddra%' THEN 'meddra'
+                    WHEN coalesce(nullif(re.vocab, ''), re.default_vocab) ILIKE '%ctcae%' THEN 'ctcae'
+                    WHEN coalesce(nullif(re.vocab, ''), re.default_vocab) ILIKE '%common terminology%' THEN 'ctcae'
+                    WHEN coalesce(nullif(re.vocab, ''), re.default_vocab) ILIKE '%snomed%' THEN 'snomed'
+                    WHEN nullif(coalesce(nullif(re.vocab, ''), re.default_vocab), '') IS NOT NULL THEN 'other'
+                    END                                          AS declared_vocab,
                 re.assessment,
-                m.pt_code
+                m.pt_code,
+                m.pt_code                                        AS llt_code,
+                CASE WHEN m.pt_code IS NOT NULL THEN 'Y' END     AS llt_currency,
+                CASE WHEN m.pt_code IS NOT NULL THEN 'PT' END    AS match_level,
+                CASE WHEN m.pt_code IS NOT NULL THEN 'exact' END AS match_method,
+                FALSE                                            AS compound
 FROM ctgov.reported_events re
          LEFT JOIN meddra.mdhier m ON lower(m.pt_name) = lower(re.adverse_event_term);
 
-WITH cte AS (SELECT DISTINCT trim(BOTH FROM
-                                  regexp_replace(regexp_replace(lower(pt_name), '[^a-z]', ' ', 'g'), '\s+', ' ',
-                                                 'g')) AS pt_name,
-                             pt_code
-             FROM meddra.mdhier
-             UNION
-             SELECT DISTINCT trim(BOTH FROM
-                                  regexp_replace(regexp_replace(lower(llt_name), '[^a-z]', ' ', 'g'), '\s+', ' ',
-                                                 'g')) AS pt_name,
-                             pt_code
-             FROM meddra.llt)
-UPDATE ctgov.reported_events_2 re
-SET pt_code = cte.pt_code
-FROM cte
-WHERE trim(BOTH FROM
-           regexp_replace(regexp_replace(lower(re.adverse_event_term), '[^a-z]', ' ', 'g'), '\s+', ' ', 'g')) =
-      cte.pt_name
-  AND re.pt_code IS NULL;
+DROP INDEX IF EXISTS ctgov.reported_events_meddra_event_type_index;
 
-DROP INDEX IF EXISTS ctgov.index_reported_events_on_event_type_2;
+CREATE INDEX reported_events_meddra_event_type_index
+    ON ctgov.reported_events_meddra (event_type);
 
-CREATE INDEX index_reported_events_on_event_type_2
-    ON ctgov.reported_events_2 (event_type);
+DROP INDEX IF EXISTS ctgov.reported_events_meddra_nct_id_index;
 
-DROP INDEX IF EXISTS ctgov.index_reported_events_on_nct_id_2;
+CREATE INDEX reported_events_meddra_nct_id_index
+    ON ctgov.reported_events_meddra (nct_id);
 
-CREATE INDEX index_reported_events_on_nct_id_2
-    ON ctgov.reported_events_2 (nct_id);
+DROP INDEX IF EXISTS ctgov.reported_events_meddra_subjects_affected_index;
 
-DROP INDEX IF EXISTS ctgov.index_reported_events_on_subjects_affected_2;
-
-CREATE INDEX index_reported_events_on_subjects_affected_2
-    ON ctgov.reported_events_2 (subjects_affected);
-
-DROP INDEX IF EXISTS ctgov.reported_events_nct_idx_2;
-
-CREATE INDEX reported_events_nct_idx_2
-    ON ctgov.reported_events_2 (nct_id);
-
-DROP TABLE IF EXISTS ctgov.reported_events;
-
-ALTER TABLE ctgov.reported_events_2
-    RENAME TO reported_events;
+CREATE INDEX reported_events_meddra_subjects_affected_index
+    ON ctgov.reported_events_meddra (subjects_affected);
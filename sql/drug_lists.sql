@@ -0,0 +1,12 @@
+-- name: drop_excluded_rxcuis
+DROP TABLE IF EXISTS ctgov.excluded_rxcuis;
+
+-- name: create_excluded_rxcuis
+CREATE TABLE ctgov.excluded_rxcuis
+(
+    rxcui INT
+);
+
+-- name: insert_excluded_rxcuis
+INSERT INTO ctgov.excluded_rxcuis (rxcui)
+SELECT DISTINCT unnest($1::INT[]);
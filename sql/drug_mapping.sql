@@ -65,12 +65,18 @@ CREATE TABLE ctgov.drug_mapping_rxcui
     drug_mapping_id INT,
     original        TEXT,
     rxcui           INT,
-    rx_str          TEXT
+    rx_str          TEXT,
+    match_method    TEXT,
+    match_detail    TEXT
 );
 
 -- name: direct_match_chembl
 INSERT INTO ctgov.drug_mapping_rxcui
-SELECT DISTINCT dm.id AS drug_mapping_id, dm.original AS original, chem.molregno AS rxcui, NULL AS rx_str
+SELECT DISTINCT dm.id           AS drug_mapping_id,
+                dm.original     AS original,
+                chem.molregno   AS rxcui,
+                NULL            AS rx_str,
+                'direct chembl' AS match_method
 FROM ctgov.drug_mapping dm
          JOIN chembl.molecule_synonyms chem ON dm.clean = lower(chem.synonyms)
 WHERE dm.clean != 'control'
@@ -94,22 +100,54 @@ ORDER BY length(chem.synonyms) DESC;
 
 -- name: direct_match_rxnconso
 INSERT INTO ctgov.drug_mapping_rxcui
-SELECT DISTINCT dm.id AS drug_mapping_id, dm.original AS original, rx2.rxcui AS rxcui, NULL AS rx_str
+SELECT DISTINCT dm.id           AS drug_mapping_id,
+                dm.original     AS original,
+                rx2.rxcui       AS rxcui,
+                NULL            AS rx_str,
+                'direct rxnorm' AS match_method
 FROM ctgov.drug_mapping dm
-         JOIN rxnorm.rxnconso rx1 ON dm.clean = lower(rx1.str)
+         JOIN ctgov.rxnorm_terms rx1 ON dm.clean = rx1.str
          JOIN rxnorm.rxnconso rx2 ON rx1.rxcui = rx2.rxcui
 WHERE rx2.tty NOT IN ('PSN', 'SY', 'TMSY', 'DF', 'ET', 'DFG')
   AND rx2.sab = 'RXNORM'
   AND dm.clean != 'control';
 
+-- name: raw_match_rxnconso
+-- Matches the lowercased names as reported, before any normalization
+INSERT INTO ctgov.drug_mapping_rxcui
+SELECT DISTINCT dm.id           AS drug_mapping_id,
+                dm.original     AS original,
+                rx2.rxcui       AS rxcui,
+                NULL            AS rx_str,
+                'direct rxnorm' AS match_method
+FROM ctgov.drug_mapping dm
+         JOIN rxnorm.rxnconso rx1 ON dm.clean = lower(rx1.str)
+         JOIN rxnorm.rxnconso rx2 ON rx1.rxcui = rx2.rxcui
+WHERE rx1.sab = 'RXNORM'
+  AND rx2.tty NOT IN ('PSN', 'SY', 'TMSY', 'DF', 'ET', 'DFG')
+  AND rx2.sab = 'RXNORM'
+  AND dm.clean != 'control';
+
+-- name: raw_match_art57
+INSERT INTO ctgov.drug_mapping_rxcui
+SELECT DISTINCT dm.id               AS drug_mapping_id,
+                dm.original         AS original,
+                unnest(string_to_array(a.rxcui, ',')::INT[]),
+                NULL                AS rx_str,
+                'direct article 57' AS match_method
+FROM ctgov.drug_mapping dm
+         JOIN ctgov.article57_rxnorm a ON dm.clean = lower(a.name)
+WHERE dm.clean != 'control';
+
 -- name: direct_match_art57
 INSERT INTO ctgov.drug_mapping_rxcui
-SELECT DISTINCT dm.id       AS drug_mapping_id,
-                dm.original AS original,
+SELECT DISTINCT dm.id               AS drug_mapping_id,
+                dm.original         AS original,
                 unnest(string_to_array(a.rxcui, ',')::INT[]),
-                NULL        AS rx_str
+                NULL                AS rx_str,
+                'direct article 57' AS match_method
 FROM ctgov.drug_mapping dm
-         JOIN ctgov.article57_rxnorm a ON dm.clean = a.name
+         JOIN ctgov.article57_rxnorm a ON dm.clean = a.clean
 WHERE dm.clean != 'control';
 
 -- name: remove_matches
@@ -123,57 +161,83 @@ WHERE cte.drug_mapping_id = dm.id;
 UPDATE ctgov.drug_mapping dm
 SET clean = NULL
 WHERE length(clean) = 1
-   OR clean IN
-      ('all participants', '', 'total', 'control', 'intervention', 'treatment', 'single', 'study', 'usual care',
-       'standard of care', 'vehicle', 'low dose', 'high dose', 'experimental', 'no treatment',
-       'quality of life assessment', 'surgery');
-
--- name: remove_cohort
-UPDATE ctgov.drug_mapping
-SET clean = regexp_replace(clean, 'cohort \d', ' ', 'g')
-WHERE clean LIKE '%cohort %';
+   OR clean = ANY ($1);
 
 -- name: sham_to_placebo
 UPDATE ctgov.drug_mapping
 SET clean = 'placebo'
-WHERE clean LIKE 'sham %'
-   OR clean IN ('plcb', 'matching placebo', 'placebo comparator', 'sham', 'sugar pill', 'placebos');
-
--- name: remove_q2w
-UPDATE ctgov.drug_mapping
-SET clean = regexp_replace(clean, 'q[0-9]w', '', 'g');
-
--- name: remove_qd
-UPDATE ctgov.drug_mapping
-SET clean = replace(clean, ' qd', '')
-WHERE clean LIKE '% qd';
+WHERE split_part(clean, ' ', 1) = ANY ($2)
+   OR clean = ANY ($1);
 
--- name: remove_non_alpha_numeric
-UPDATE ctgov.drug_mapping
-SET clean = regexp_replace(original, '[^a-z0-9]', ' ', 'g');
+-- name: find_terms_to_clean
+SELECT id, clean AS term
+FROM ctgov.drug_mapping
+WHERE clean IS NOT NULL;
 
--- name: remove_group
-UPDATE ctgov.drug_mapping
-SET clean = replace(clean, 'group', '');
+-- name: update_clean
+UPDATE ctgov.drug_mapping dm
+SET clean = t.clean
+FROM unnest($1::BIGINT[], $2::TEXT[]) AS t(id, clean)
+WHERE dm.id = t.id;
+
+-- name: find_art57_names
+SELECT DISTINCT name AS id, name AS term
+FROM ctgov.article57_rxnorm
+WHERE name IS NOT NULL;
+
+-- name: update_art57_names
+UPDATE ctgov.article57_rxnorm a
+SET clean = t.clean
+FROM unnest($1::TEXT[], $2::TEXT[]) AS t(name, clean)
+WHERE a.name = t.name;
+
+-- name: drop_rxnorm_terms
+DROP TABLE IF EXISTS ctgov.rxnorm_terms;
+
+-- name: create_rxnorm_terms
+CREATE TABLE ctgov.rxnorm_terms
+(
+    rxcui INT,
+    sab   TEXT,
+    tty   TEXT,
+    str   TEXT
+);
 
--- name: remove_spaces
-UPDATE ctgov.drug_mapping
-SET clean = replace(clean, '  ', ' ');
+-- name: find_rxnorm_strings
+SELECT DISTINCT rxcui, sab, tty, str
+FROM rxnorm.rxnconso
+WHERE sab = 'RXNORM';
 
--- name: remove_group
-UPDATE ctgov.drug_mapping
-SET clean = replace(clean, 'group', '');
+-- name: insert_rxnorm_terms
+INSERT INTO ctgov.rxnorm_terms (rxcui, sab, tty, str)
+SELECT *
+FROM unnest($1::INT[], $2::TEXT[], $3::TEXT[], $4::TEXT[]);
 
--- name: remove_arm
-UPDATE ctgov.drug_mapping
-SET clean = replace(clean, 'arm', '')
-WHERE clean LIKE 'arm %'
-   OR clean LIKE '% arm'
-   OR clean LIKE '% arm %';
+-- name: rxnorm_terms_index
+CREATE INDEX rxnorm_terms_str_index
+    ON ctgov.rxnorm_terms (str);
 
--- name: trim
-UPDATE ctgov.drug_mapping
-SET clean = trim(BOTH FROM clean);
+-- name: find_ingredient_rxcuis
+SELECT DISTINCT str, rxcui
+FROM ctgov.rxnorm_terms
+WHERE sab = 'RXNORM'
+  AND tty = 'IN'
+  AND str = ANY ($1)
+  AND rxcui NOT IN (SELECT rxcui FROM ctgov.excluded_rxcuis);
+
+-- name: insert_matches
+INSERT INTO ctgov.drug_mapping_rxcui (drug_mapping_id, original, rxcui, match_method, match_detail)
+SELECT t.id, dm.original, t.rxcui, $4, t.detail
+FROM unnest($1::BIGINT[], $2::INT[], $3::TEXT[]) AS t(id, rxcui, detail)
+         JOIN ctgov.drug_mapping dm ON dm.id = t.id;
+
+-- name: find_fuzzy_candidates
+SELECT DISTINCT str, rxcui
+FROM ctgov.rxnorm_terms
+WHERE sab = 'RXNORM'
+  AND tty IN ('IN', 'BN')
+  AND length(str) >= $1
+  AND rxcui NOT IN (SELECT rxcui FROM ctgov.excluded_rxcuis);
 
 -- name: pre_and_append_spaces
 UPDATE ctgov.drug_mapping
@@ -204,6 +268,58 @@ INTO ctgov.drug_mapping_rxcui
 SELECT *
 FROM cte;
 
+-- name: drop_brand_resolution
+DROP TABLE IF EXISTS ctgov.brand_resolution;
+
+-- name: create_brand_resolution
+-- Every match to an RxNorm brand name (BN) with the ingredients it resolves to. A brand is ambiguous when its branded
+-- drugs (SBD) do not all contain the same set of ingredients, read along 'SBD has_ingredient BN',
+-- 'SBD tradename_of SCD', 'SCD consists_of SCDC' and 'SCDC has_ingredient IN'.
+CREATE TABLE ctgov.brand_resolution AS
+WITH brands AS (SELECT DISTINCT dmr.drug_mapping_id, dm.original, dmr.match_method, dmr.rxcui, c.str
+                FROM ctgov.drug_mapping_rxcui dmr
+                         JOIN ctgov.drug_mapping dm ON dm.id = dmr.drug_mapping_id
+                         JOIN rxnorm.rxnconso c ON c.rxcui = dmr.rxcui
+                WHERE c.sab = 'RXNORM'
+                  AND c.tty = 'BN'),
+     sbd_ingredients AS (SELECT bn.rxcui1 AS brand_rxcui, bn.rxcui2 AS sbd, array_agg(DISTINCT ing.rxcui1) AS ingredients
+                         FROM rxnorm.rxnrel bn
+                                  JOIN rxnorm.rxnrel scd ON scd.rxcui2 = bn.rxcui2 AND scd.rela = 'tradename_of'
+                                  JOIN rxnorm.rxnrel scdc ON scdc.rxcui2 = scd.rxcui1 AND scdc.rela = 'consists_of'
+                                  JOIN rxnorm.rxnrel ing ON ing.rxcui2 = scdc.rxcui1 AND ing.rela = 'has_ingredient'
+                                  JOIN rxnorm.rxnconso i ON i.rxcui = ing.rxcui1
+                         WHERE bn.rela = 'has_ingredient'
+                           AND bn.sab = 'RXNORM'
+                           AND bn.rxcui1 IN (SELECT rxcui FROM brands)
+                           AND i.sab = 'RXNORM'
+                           AND i.tty = 'IN'
+                         GROUP BY bn.rxcui1, bn.rxcui2),
+     ingredient_sets AS (SELECT brand_rxcui, count(DISTINCT ingredients) AS sets
+                         FROM sbd_ingredients
+                         GROUP BY brand_rxcui)
+SELECT DISTINCT b.drug_mapping_id,
+                b.original,
+                b.match_method,
+                b.rxcui                 AS brand_rxcui,
+                b.str                   AS brand_str,
+                p.ingredient_rxcui      AS in_rxcui,
+                i.str                   AS in_str,
+                p.path,
+                coalesce(s.sets, 0)     AS ingredient_sets,
+                coalesce(s.sets, 0) > 1 AS ambiguous
+FROM brands b
+         LEFT JOIN ctgov.rxnorm_ingredient_paths p ON p.rxcui = b.rxcui AND p.ingredient_tty = 'IN'
+         LEFT JOIN rxnorm.rxnconso i ON i.rxcui = p.ingredient_rxcui AND i.sab = 'RXNORM' AND i.tty = 'IN'
+         LEFT JOIN ingredient_sets s ON s.brand_rxcui = b.rxcui;
+
+-- name: remove_ambiguous_brands
+DELETE
+FROM ctgov.drug_mapping_rxcui dmr
+    USING ctgov.brand_resolution br
+WHERE br.ambiguous
+  AND dmr.drug_mapping_id = br.drug_mapping_id
+  AND dmr.rxcui = br.brand_rxcui;
+
 -- name: drop_join_table_1
 DROP TABLE IF EXISTS ctgov.interventions_rxnorm;
 -- name: drop_join_table_2
@@ -270,9 +386,16 @@ CREATE TABLE ctgov.rg_desc_mapping AS
      GROUP BY lower(m.rg_desc));
 
 
--- name: remove_non_alpha_numeric_rg
-UPDATE ctgov.rg_desc_mapping
-SET description = regexp_replace(description, '[^a-z0-9]', ' ', 'g');
+-- name: find_descriptions_to_clean
+SELECT id, description AS term
+FROM ctgov.rg_desc_mapping
+WHERE description IS NOT NULL;
+
+-- name: update_descriptions
+UPDATE ctgov.rg_desc_mapping rg
+SET description = t.description
+FROM unnest($1::BIGINT[], $2::TEXT[]) AS t(id, description)
+WHERE rg.id = t.id;
 
 -- name: pre_and_append_spaces_rg
 UPDATE ctgov.rg_desc_mapping
@@ -335,93 +458,108 @@ FROM ctgov.matches m
          LEFT JOIN ctgov.interventions_rxnorm ir ON io.intervention_id = ir.id
 WHERE ir.id IS NULL;
 
+-- name: drop_ingredient_paths
+DROP TABLE IF EXISTS ctgov.rxnorm_ingredient_paths;
+
+-- name: create_ingredient_paths
+-- Walks from every concept attached to a result group to its ingredients. A row in rxnrel reads
+-- 'rxcui2 rela rxcui1', e.g. 'SBD tradename_of SCD', 'SCD consists_of SCDC', 'SCDC has_ingredient IN',
+-- 'PIN form_of IN' (the inverse of 'IN has_form PIN'), 'SCD has_ingredients MIN' and 'MIN has_part IN'.
+-- The path taken is stored as 'TTY rxcui rela TTY rxcui ...', only the shortest path is kept. Every mapped concept is
+-- walked, not only those attached to a result group, so the brand resolution report can use the paths as well.
+CREATE TABLE ctgov.rxnorm_ingredient_paths AS
+WITH RECURSIVE concepts AS (SELECT DISTINCT rxcui, tty, str
+                            FROM rxnorm.rxnconso
+                            WHERE sab = 'RXNORM'
+                              AND tty IN ('IN', 'PIN', 'MIN', 'BN', 'SCD', 'SBD', 'SCDC', 'SBDC', 'SCDF', 'SBDF',
+                                          'SCDG', 'SBDG', 'GPCK', 'BPCK')),
+               paths AS (SELECT c.rxcui, c.rxcui AS target, c.tty, concat(c.tty, ' ', c.rxcui) AS path, 0 AS depth
+                         FROM (SELECT rxcui
+                               FROM ctgov.result_groups_rxnorm
+                               UNION
+                               SELECT rxcui
+                               FROM ctgov.drug_mapping_rxcui) rx
+                                  JOIN concepts c ON c.rxcui = rx.rxcui
+                         UNION
+                         SELECT p.rxcui,
+                                c.rxcui,
+                                c.tty,
+                                concat(p.path, ' ', rel.rela, ' ', c.tty, ' ', c.rxcui),
+                                p.depth + 1
+                         FROM paths p
+                                  JOIN rxnorm.rxnrel rel ON rel.rxcui2 = p.target
+                                  JOIN concepts c ON c.rxcui = rel.rxcui1
+                         WHERE rel.sab = 'RXNORM'
+                           AND rel.rela IN ('tradename_of', 'consists_of', 'has_ingredient', 'form_of',
+                                            'has_ingredients', 'has_part', 'contains')
+                           AND p.tty != 'IN'
+                           AND p.depth < 5)
+SELECT DISTINCT ON (p.rxcui, p.target) p.rxcui,
+                                       p.target AS ingredient_rxcui,
+                                       p.tty    AS ingredient_tty,
+                                       p.path
+FROM paths p
+WHERE p.tty IN ('IN', 'MIN')
+ORDER BY p.rxcui, p.target, p.depth;
+
 -- name: drop_final_rg_in_table
 DROP TABLE IF EXISTS ctgov.result_group_ingredient;
 
 -- name: create_final_rg_in_table
+-- combination_rxcui holds the multiple ingredient (MIN) concept when the ingredient was given as part of a fixed
+-- combination product, it stays empty for products containing a single ingredient.
 CREATE TABLE ctgov.result_group_ingredient AS
-WITH cte AS (SELECT DISTINCT r.nct_id,
-                             r.id,
-                             rx.rxcui AS rxcui,
-                             rc.str,
-                             rc.tty,
-                             rx.rxcui AS rxcui_in
-             FROM ctgov.result_groups r
-                      JOIN ctgov.reported_events re ON r.nct_id = re.nct_id
-                      JOIN ctgov.designs d ON r.nct_id = d.nct_id
-                      JOIN ctgov.result_groups_rxnorm rx ON rx.id = r.id
-                      JOIN rxnorm.rxnconso rc ON rc.rxcui = rx.rxcui
-             WHERE r.ctgov_group_code LIKE 'E%'
-               AND rc.tty NOT IN ('IN', 'PSN', 'SY', 'TMSY', 'DF', 'ET', 'DFG')
-               AND rc.sab = 'RXNORM'
-               AND re.subjects_affected > 0
-               AND r.nct_id IS NOT NULL
-               AND rx.rxcui NOT IN (1001007, 890964, 411, 11295, 1736009, 107129))
-SELECT DISTINCT cte.nct_id,
-                cte.id,
-                cte.rxcui             AS rxcui,
-                cte.str,
-                cte.tty,
-                r.rxcui               AS in_rxcui,
-                r.str                 AS in_str,
-                cast(NULL AS VARCHAR) AS dose
-FROM rxnorm.rxnrel rel
-         JOIN rxnorm.rxnconso r ON rel.rxcui2 = r.rxcui
-         JOIN cte ON cte.rxcui = rel.rxcui1
-WHERE r.tty = 'IN'
-  AND r.sab = 'RXNORM'
-UNION
+WITH combinations AS (SELECT DISTINCT ON (rxcui) rxcui, ingredient_rxcui
+                      FROM ctgov.rxnorm_ingredient_paths
+                      WHERE ingredient_tty = 'MIN'
+                      ORDER BY rxcui, length(path))
 SELECT DISTINCT r.nct_id,
                 r.id,
-                rx.rxcui              AS rxcui,
+                rx.rxcui,
                 rc.str,
                 rc.tty,
-                rx.rxcui              AS in_rxcui,
-                rc.str                AS in_str,
+                p.ingredient_rxcui    AS in_rxcui,
+                i.str                 AS in_str,
+                p.path,
+                c.ingredient_rxcui    AS combination_rxcui,
                 cast(NULL AS VARCHAR) AS dose
 FROM ctgov.result_groups r
          JOIN ctgov.reported_events re ON r.nct_id = re.nct_id
          JOIN ctgov.designs d ON r.nct_id = d.nct_id
          JOIN ctgov.result_groups_rxnorm rx ON rx.id = r.id
          JOIN rxnorm.rxnconso rc ON rc.rxcui = rx.rxcui
+         JOIN ctgov.rxnorm_ingredient_paths p ON p.rxcui = rx.rxcui
+         JOIN rxnorm.rxnconso i ON i.rxcui = p.ingredient_rxcui
+         LEFT JOIN combinations c ON c.rxcui = rx.rxcui
 WHERE r.ctgov_group_code LIKE 'E%'
-  AND rc.tty = 'IN'
+  AND rc.tty NOT IN ('PSN', 'SY', 'TMSY', 'DF', 'ET', 'DFG')
   AND rc.sab = 'RXNORM'
+  AND p.ingredient_tty = 'IN'
+  AND i.tty = 'IN'
+  AND i.sab = 'RXNORM'
   AND re.subjects_affected > 0
   AND r.nct_id IS NOT NULL
-  AND rx.rxcui NOT IN (1001007, 890964, 411, 11295, 1736009, 107129)
-UNION
-(WITH cte AS (SELECT DISTINCT r.nct_id,
-                              r.id,
-                              rx.rxcui AS rxcui,
-                              rc.str,
-                              rc.tty,
-                              rx.rxcui AS rxcui_in
-              FROM ctgov.result_groups r
-                       JOIN ctgov.reported_events re ON r.nct_id = re.nct_id
-                       JOIN ctgov.designs d ON r.nct_id = d.nct_id
-                       JOIN ctgov.result_groups_rxnorm rx ON rx.id = r.id
-                       JOIN rxnorm.rxnconso rc ON rc.rxcui = rx.rxcui
-              WHERE r.ctgov_group_code LIKE 'E%'
-                AND rc.tty IN ('SCD', 'SBDG', 'SBDF', 'SBDC', 'SBD')
-                AND rc.sab = 'RXNORM'
-                AND re.subjects_affected > 0
-                AND r.nct_id IS NOT NULL
-                AND rx.rxcui NOT IN (1001007, 890964, 411, 11295, 1736009, 107129))
- SELECT DISTINCT cte.nct_id,
-                 cte.id,
-                 cte.rxcui             AS rxcui,
-                 cte.str,
-                 cte.tty,
-                 r.rxcui               AS in_rxcui,
-                 r.str                 AS in_str,
-                 cast(NULL AS VARCHAR) AS dose
- FROM rxnorm.rxnrel rel1
-          JOIN rxnorm.rxnrel rel2 ON rel1.rxcui2 = rel2.rxcui1
-          JOIN rxnorm.rxnconso r ON rel2.rxcui2 = r.rxcui
-          JOIN cte ON cte.rxcui = rel1.rxcui1
- WHERE r.tty = 'IN'
-   AND r.sab = 'RXNORM');
+  AND rx.rxcui NOT IN (SELECT rxcui FROM ctgov.excluded_rxcuis);
+
+-- name: drop_final_rg_combination_table
+DROP TABLE IF EXISTS ctgov.result_group_combination;
+
+-- name: create_final_rg_combination_table
+CREATE TABLE ctgov.result_group_combination AS
+SELECT rgi.nct_id,
+       rgi.id,
+       rgi.rxcui,
+       rgi.str,
+       rgi.tty,
+       rgi.combination_rxcui,
+       m.str                                              AS combination_str,
+       array_agg(DISTINCT rgi.in_rxcui)                   AS ingredient_rxcuis,
+       array_agg(DISTINCT rgi.in_str ORDER BY rgi.in_str) AS ingredient_strs
+FROM ctgov.result_group_ingredient rgi
+         JOIN rxnorm.rxnconso m ON m.rxcui = rgi.combination_rxcui
+WHERE m.tty = 'MIN'
+  AND m.sab = 'RXNORM'
+GROUP BY rgi.nct_id, rgi.id, rgi.rxcui, rgi.str, rgi.tty, rgi.combination_rxcui, m.str;
 
 -- name: get_dose_1
 WITH cte AS (SELECT DISTINCT rgi.rxcui, substring(rx.str FROM '\s([0-9].*)$') AS dose
@@ -436,13 +574,12 @@ WHERE cte.rxcui = rgi.rxcui;
 
 
 -- name: find_rxconso_terms_for_tty
-SELECT DISTINCT trim(BOTH FROM
-                     regexp_replace(regexp_replace(lower(str), '[^a-z0-9]', ' ', 'g'), '\s+', ' ', 'g')) AS str,
-                rxcui
-FROM rxnorm.rxnconso
+SELECT DISTINCT str, rxcui
+FROM ctgov.rxnorm_terms
 WHERE sab = 'RXNORM'
+  AND str != ''
   AND tty = $1
-  AND rxcui NOT IN (1001007, 890964, 411, 11295, 1736009, 107129);
+  AND rxcui NOT IN (SELECT rxcui FROM ctgov.excluded_rxcuis);
 
 -- name: find_terms_to_map
 SELECT DISTINCT id, clean
@@ -0,0 +1,12 @@
+-- name: find_columns
+SELECT cast(column_name AS TEXT) AS column_name,
+       cast(data_type AS TEXT)   AS data_type
+FROM information_schema.columns
+WHERE table_schema = 'ctgov'
+  AND table_name = $1
+ORDER BY ordinal_position;
+
+-- name: find_run_log
+SELECT run_id, key, value
+FROM ctgov.dracula_run_log
+ORDER BY run_id, key;
@@ -4,99 +4,263 @@ DROP TABLE IF EXISTS ctgov.rg_meddra_map;
 -- name: create_table
 CREATE TABLE ctgov.rg_meddra_map
 (
-    original TEXT,
-    standard TEXT,
-    pt_code  INTEGER
+    original     TEXT,
+    organ_system TEXT,
+    standard     TEXT,
+    pt_code      INTEGER,
+    llt_code     INTEGER,
+    llt_currency TEXT,
+    match_level  TEXT,
+    match_method TEXT
 );
 
+-- name: drop_compound_table
+DROP TABLE IF EXISTS ctgov.rg_meddra_compound_map;
 
--- name: update_general_disorders
-UPDATE ctgov.reported_events
-SET organ_system = 'General disorders and administration site conditions'
-WHERE organ_system = 'General disorders';
+-- name: create_compound_table
+CREATE TABLE ctgov.rg_meddra_compound_map
+(
+    original     TEXT,
+    component    TEXT,
+    standard     TEXT,
+    pt_code      INTEGER,
+    llt_code     INTEGER,
+    llt_currency TEXT,
+    match_level  TEXT
+);
+
+-- name: drop_candidates_table
+DROP TABLE IF EXISTS ctgov.rg_meddra_candidates;
+
+-- name: create_candidates_table
+CREATE TABLE ctgov.rg_meddra_candidates
+(
+    original     TEXT,
+    organ_system TEXT,
+    rank         SMALLINT,
+    standard     TEXT,
+    pt_code      INTEGER,
+    llt_code     INTEGER,
+    match_level  TEXT,
+    sift3        REAL,
+    distance     INTEGER,
+    score        INTEGER,
+    tied         BOOLEAN
+);
 
 -- name: find_unknown_terms
-SELECT lower(e.adverse_event_term)
-FROM ctgov.reported_events e
-WHERE lower(e.organ_system) = $1
+-- The free text terms, those whose declared vocabulary, or '' for none, is in $1
+SELECT DISTINCT e.term, lower(e.organ_system) AS organ_system
+FROM ctgov.reported_events_meddra e
+WHERE e.organ_system != 'Total'
   AND e.pt_code IS NULL
-  AND e.adverse_event_term IS NOT NULL
-GROUP BY lower(e.adverse_event_term);
+  AND NOT e.compound
+  AND e.term IS NOT NULL
+  AND coalesce(e.declared_vocab, '') = ANY ($1);
+
+-- name: find_unmapped_terms
+SELECT DISTINCT e.term
+FROM ctgov.reported_events_meddra e
+WHERE e.pt_code IS NULL
+  AND NOT e.compound
+  AND e.term IS NOT NULL;
+
+-- name: find_unmapped_free_text_terms
+-- The free text terms, those whose declared vocabulary, or '' for none, is in $1
+SELECT DISTINCT e.term
+FROM ctgov.reported_events_meddra e
+WHERE e.pt_code IS NULL
+  AND NOT e.compound
+  AND e.term IS NOT NULL
+  AND coalesce(e.declared_vocab, '') = ANY ($1);
+
+-- name: find_unmapped_ctcae_terms
+-- The free text terms, those whose declared vocabulary, or '' for none, is in $1, and the terms carrying a CTCAE grade
+SELECT DISTINCT e.term
+FROM ctgov.reported_events_meddra e
+WHERE e.pt_code IS NULL
+  AND NOT e.compound
+  AND e.term IS NOT NULL
+  AND (coalesce(e.declared_vocab, '') = ANY ($1) OR e.ctcae_grade IS NOT NULL);
+
+-- name: find_snomed_matches
+-- Terms declared as SNOMED CT that are the name of a SNOMED CT concept equivalent to a single MedDRA preferred term
+SELECT e.term, min(cast(m.concept_code AS INTEGER)) AS pt_code
+FROM (SELECT DISTINCT term
+      FROM ctgov.reported_events_meddra
+      WHERE pt_code IS NULL
+        AND NOT compound
+        AND declared_vocab = 'snomed') e
+         JOIN omop.concept s
+              ON s.vocabulary_id = 'SNOMED'
+                  AND lower(s.concept_name) = e.term
+         JOIN omop.concept_relationship r
+              ON r.concept_id_2 = s.concept_id
+                  AND r.relationship_id = 'MedDRA - SNOMED eq'
+                  AND r.invalid_reason IS NULL
+         JOIN omop.concept m
+              ON m.concept_id = r.concept_id_1
+                  AND m.vocabulary_id = 'MedDRA'
+                  AND m.concept_class_id = 'PT'
+GROUP BY e.term
+HAVING count(DISTINCT m.concept_code) = 1;
+
+-- name: update_ctcae_grades
+UPDATE ctgov.reported_events_meddra re
+SET term        = t.term,
+    ctcae_grade = t.grade
+FROM unnest($1::TEXT[], $2::TEXT[], $3::SMALLINT[]) AS t(original, term, grade)
+WHERE re.term = t.original
+  AND re.pt_code IS NULL;
 
 -- name: find_all_pts
-SELECT DISTINCT trim(BOTH FROM
-                     regexp_replace(regexp_replace(lower(pt_name), '[^a-z]', ' ', 'g'), '\s+', ' ', 'g')) AS pt_name,
-                pt_code
+SELECT DISTINCT lower(pt_name) AS pt_name, pt_code, pt_code AS llt_code, 'Y' AS llt_currency, 'PT' AS match_level
 FROM meddra.mdhier
 UNION
-SELECT DISTINCT trim(BOTH FROM
-                     regexp_replace(regexp_replace(lower(llt_name), '[^a-z]', ' ', 'g'), '\s+', ' ', 'g')) AS pt_name,
-                pt_code
-FROM meddra.llt;
-
--- name: find_all_unknown_terms
-SELECT trim(BOTH FROM regexp_replace(regexp_replace(lower(e.adverse_event_term), '[^a-z]', ' ', 'g'), '\s+', ' ', 'g'))
-FROM ctgov.reported_events e
-WHERE e.organ_system != 'Total'
-  AND e.pt_code IS NULL
-  AND e.adverse_event_term IS NOT NULL
-  AND e.subjects_affected > 0
-GROUP BY trim(BOTH FROM
-              regexp_replace(regexp_replace(lower(e.adverse_event_term), '[^a-z]', ' ', 'g'), '\s+', ' ', 'g'))
-HAVING count(*) > 1;
-
--- name: find_pts
-SELECT DISTINCT lower(pt_name) AS pt_name, pt_code
-FROM meddra.mdhier
-WHERE lower(soc_name) = $1;
+SELECT DISTINCT lower(llt_name) AS pt_name, pt_code, llt_code, llt_currency::TEXT, 'LLT' AS match_level
+FROM meddra.llt
+WHERE llt_code != pt_code;
 
--- name: find_all_socs
-SELECT DISTINCT lower(soc_name)
+-- name: find_pt_socs
+SELECT DISTINCT pt_code, lower(soc_name) AS soc_name
 FROM meddra.mdhier;
 
+-- name: insert_mappings
+INSERT INTO ctgov.rg_meddra_map (original, organ_system, standard, pt_code, llt_code, llt_currency, match_level,
+                                 match_method)
+SELECT *, $8::TEXT
+FROM unnest($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::INT[], $5::INT[], $6::TEXT[], $7::TEXT[]);
 
--- name: insert_wild_pt_code_1
-UPDATE ctgov.reported_events re
-SET pt_code = m.pt_code
-FROM ctgov.rg_meddra_map m
-WHERE m.original = lower(re.adverse_event_term)
-  AND re.pt_code IS NULL;
+-- name: insert_candidates
+INSERT INTO ctgov.rg_meddra_candidates (original, organ_system, rank, standard, pt_code, llt_code, match_level, sift3,
+                                        distance, score, tied)
+SELECT *
+FROM unnest($1::TEXT[], $2::TEXT[], $3::SMALLINT[], $4::TEXT[], $5::INT[], $6::INT[], $7::TEXT[], $8::REAL[],
+            $9::INT[], $10::INT[], $11::BOOLEAN[]);
 
--- name: insert_wild_pt_code_2
-UPDATE ctgov.reported_events re
-SET pt_code = m.pt_code
+-- name: insert_compound_mappings
+INSERT INTO ctgov.rg_meddra_compound_map (original, component, standard, pt_code, llt_code, llt_currency, match_level)
+SELECT *
+FROM unnest($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::INT[], $5::INT[], $6::TEXT[], $7::TEXT[]);
+
+-- name: mark_compound_terms
+UPDATE ctgov.reported_events_meddra re
+SET compound = TRUE
+FROM (SELECT DISTINCT original FROM ctgov.rg_meddra_compound_map) c
+WHERE c.original = re.term
+  AND re.pt_code IS NULL
+  AND coalesce(re.declared_vocab, '') = ANY ($1);
+
+-- name: insert_pt_codes
+-- Exact matches apply to every term, the other methods only to the terms routed to them: SNOMED CT matches to terms
+-- declared as SNOMED CT, CTCAE matches as in find_unmapped_ctcae_terms and the rest to the free text vocabularies in $1
+UPDATE ctgov.reported_events_meddra re
+SET pt_code      = m.pt_code,
+    llt_code     = m.llt_code,
+    llt_currency = m.llt_currency,
+    match_level  = m.match_level,
+    match_method = m.match_method
 FROM ctgov.rg_meddra_map m
-WHERE m.original =
-      trim(BOTH FROM regexp_replace(regexp_replace(lower(re.adverse_event_term), '[^a-z]', ' ', 'g'), '\s+', ' ', 'g'))
-  AND re.pt_code IS NULL;
+WHERE m.original = re.term
+  AND (m.organ_system IS NULL OR m.organ_system = lower(re.organ_system))
+  AND re.pt_code IS NULL
+  AND CASE m.match_method
+          WHEN 'exact' THEN TRUE
+          WHEN 'snomed' THEN re.declared_vocab = 'snomed'
+          WHEN 'ctcae' THEN coalesce(re.declared_vocab, '') = ANY ($1) OR re.ctcae_grade IS NOT NULL
+          ELSE coalesce(re.declared_vocab, '') = ANY ($1)
+      END;
+
+-- name: drop_link_table
+DROP TABLE IF EXISTS ctgov.reported_event_pt;
+
+-- name: create_link_table
+-- One row per reported event and preferred term, compound events have a row for every component.
+CREATE TABLE ctgov.reported_event_pt AS
+SELECT re.id              AS reported_event_id,
+       re.pt_code,
+       re.llt_code,
+       re.llt_currency,
+       re.match_level,
+       cast(NULL AS TEXT) AS component,
+       FALSE              AS compound
+FROM ctgov.reported_events_meddra re
+WHERE re.pt_code IS NOT NULL
+UNION ALL
+SELECT re.id,
+       c.pt_code,
+       c.llt_code,
+       c.llt_currency,
+       c.match_level,
+       c.component,
+       TRUE
+FROM ctgov.reported_events_meddra re
+         JOIN ctgov.rg_meddra_compound_map c ON c.original = re.term
+WHERE re.compound;
+
+-- name: drop_hierarchy_table
+DROP TABLE IF EXISTS ctgov.reported_event_hierarchy;
+
+-- name: create_hierarchy_table
+-- One row per reported event and MedDRA hierarchy path, a preferred term linked to more than one SOC has a row for
+-- every SOC and primary_soc_fg = 'Y' marks the primary one.
+CREATE TABLE ctgov.reported_event_hierarchy AS
+SELECT re.id AS reported_event_id,
+       re.nct_id,
+       re.result_group_id,
+       re.adverse_event_term,
+       p.compound,
+       p.component,
+       p.llt_code,
+       l.llt_name,
+       h.pt_code,
+       h.pt_name,
+       h.hlt_code,
+       h.hlt_name,
+       h.hlgt_code,
+       h.hlgt_name,
+       h.soc_code,
+       h.soc_name,
+       h.primary_soc_fg
+FROM ctgov.reported_event_pt p
+         JOIN ctgov.reported_events_meddra re ON re.id = p.reported_event_id
+         JOIN meddra.mdhier h ON h.pt_code = p.pt_code
+         LEFT JOIN meddra.llt l ON l.llt_code = p.llt_code;
+
+-- name: hierarchy_pt_index
+CREATE INDEX reported_event_hierarchy_pt_index
+    ON ctgov.reported_event_hierarchy (pt_code);
+
+-- name: hierarchy_soc_index
+CREATE INDEX reported_event_hierarchy_soc_index
+    ON ctgov.reported_event_hierarchy (soc_code);
+
+-- name: drop_vocab_report
+DROP TABLE IF EXISTS ctgov.meddra_vocab_report;
 
+-- name: create_vocab_report
+-- The events per declared vocabulary and term and how they were matched. Terms declared as MedDRA should match a MedDRA
+-- term exactly, terms declared as CTCAE exactly or through the CTCAE correspondence.
+CREATE TABLE ctgov.meddra_vocab_report AS
+SELECT re.declared_vocab,
+       coalesce(nullif(re.vocab, ''), re.default_vocab)                 AS vocab,
+       re.term,
+       CASE WHEN re.compound THEN 'compound' ELSE re.match_method END AS match_method,
+       CASE
+           WHEN re.declared_vocab = 'meddra' AND NOT re.compound
+               AND re.match_method IS DISTINCT FROM 'exact' THEN 'not a MedDRA term'
+           WHEN re.declared_vocab = 'ctcae' AND NOT re.compound
+               AND coalesce(re.match_method, '') NOT IN ('exact', 'ctcae') THEN 'not a CTCAE term'
+           END                                                          AS finding,
+       count(*)                                                         AS events
+FROM ctgov.reported_events_meddra re
+WHERE re.organ_system IS DISTINCT FROM 'Total'
+  AND re.term IS NOT NULL
+GROUP BY 1, 2, 3, 4, 5;
 
--- name: catch_terms_including_the_word_or
-WITH cte1 AS (SELECT DISTINCT trim(BOTH FROM
-                                   regexp_replace(regexp_replace(lower(s.either), '[^a-z]', ' ', 'g'), '\s+', ' ',
-                                                  'g')) AS loose,
-                              adverse_event_term
-              FROM ctgov.reported_events t,
-                   unnest(string_to_array(t.adverse_event_term, ' or ')) s(either)
-              WHERE pt_code IS NULL
-                AND adverse_event_term LIKE '% or %'
-              ORDER BY adverse_event_term),
-     cte2 AS (SELECT DISTINCT trim(BOTH FROM
-                                   regexp_replace(regexp_replace(lower(pt_name), '[^a-z]', ' ', 'g'), '\s+', ' ',
-                                                  'g')) AS pt_name,
-                              pt_code
-              FROM meddra.mdhier
-              UNION
-              SELECT DISTINCT trim(BOTH FROM
-                                   regexp_replace(regexp_replace(lower(llt_name), '[^a-z]', ' ', 'g'), '\s+', ' ',
-                                                  'g')) AS pt_name,
-                              pt_code
-              FROM meddra.llt)
-INSERT
-INTO ctgov.rg_meddra_map (original, standard, pt_code)
-SELECT cte1.adverse_event_term AS original,
-       cte2.pt_name            AS standard,
-       pt_code                 AS pt_code
-FROM cte1
-         JOIN cte2 ON cte1.loose = cte2.pt_name
-GROUP BY cte1.adverse_event_term, cte2.pt_name, pt_code;
+-- name: count_vocab_findings
+SELECT declared_vocab, finding, count(*) AS terms, sum(events)::BIGINT AS events
+FROM ctgov.meddra_vocab_report
+WHERE finding IS NOT NULL
+GROUP BY declared_vocab, finding
+ORDER BY declared_vocab;
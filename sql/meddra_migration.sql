@@ -0,0 +1,46 @@
+-- name: drop_migration_table
+DROP TABLE IF EXISTS ctgov.meddra_migration;
+
+-- name: create_migration_table
+-- Looks up the LLT of every mapped event in the current MedDRA release. An LLT that moved to another PT follows its
+-- new PT, a non-current LLT keeps the PT it is still linked to. When the LLT is gone the PT is kept if it still exists.
+CREATE TABLE ctgov.meddra_migration AS
+SELECT re.id                           AS reported_event_id,
+       re.pt_code                      AS old_pt_code,
+       re.llt_code                     AS old_llt_code,
+       coalesce(l.pt_code, h.pt_code)  AS new_pt_code,
+       l.llt_code                      AS new_llt_code,
+       l.llt_currency::TEXT            AS llt_currency,
+       CASE
+           WHEN l.llt_code IS NULL AND h.pt_code IS NULL THEN 'unresolved'
+           WHEN l.llt_code IS NULL THEN 'llt removed'
+           WHEN l.pt_code != re.pt_code THEN 'moved'
+           WHEN l.llt_currency != 'Y' THEN 'non-current'
+           ELSE 'unchanged'
+           END                         AS status
+FROM ctgov.reported_events_meddra re
+         LEFT JOIN meddra.llt l ON l.llt_code = coalesce(re.llt_code, re.pt_code)
+         LEFT JOIN (SELECT DISTINCT pt_code FROM meddra.mdhier) h ON h.pt_code = re.pt_code
+WHERE re.pt_code IS NOT NULL;
+
+-- name: count_by_status
+SELECT status, count(*) AS events
+FROM ctgov.meddra_migration
+GROUP BY status
+ORDER BY status;
+
+-- name: update_compound_map
+UPDATE ctgov.rg_meddra_compound_map c
+SET pt_code      = l.pt_code,
+    llt_currency = l.llt_currency::TEXT
+FROM meddra.llt l
+WHERE l.llt_code = c.llt_code;
+
+-- name: update_reported_events
+UPDATE ctgov.reported_events_meddra re
+SET pt_code      = m.new_pt_code,
+    llt_code     = coalesce(m.new_llt_code, m.new_pt_code),
+    llt_currency = coalesce(m.llt_currency, 'Y')
+FROM ctgov.meddra_migration m
+WHERE m.reported_event_id = re.id
+  AND m.status != 'unresolved';
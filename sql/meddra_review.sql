@@ -0,0 +1,27 @@
+-- name: find_review_terms
+-- The low confidence terms: those whose two best candidates tie and those whose best candidate scores within the
+-- margin below or above the maximum score, $1 and $2
+SELECT c.original                  AS term,
+       coalesce(c.organ_system, '') AS organ_system,
+       count(re.id)                AS events
+FROM (SELECT DISTINCT original, organ_system
+      FROM ctgov.rg_meddra_candidates
+      WHERE rank = 1
+        AND (tied OR score BETWEEN $1 AND $2)) c
+         JOIN ctgov.reported_events_meddra re
+              ON re.term = c.original
+                  AND (c.organ_system IS NULL OR lower(re.organ_system) = c.organ_system)
+GROUP BY c.original, c.organ_system
+ORDER BY events DESC, term;
+
+-- name: find_candidates
+SELECT original                  AS term,
+       coalesce(organ_system, '') AS organ_system,
+       standard,
+       pt_code,
+       llt_code,
+       match_level,
+       distance,
+       score
+FROM ctgov.rg_meddra_candidates
+ORDER BY original, organ_system, rank;
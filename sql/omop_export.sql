@@ -0,0 +1,99 @@
+-- name: create_schema
+CREATE SCHEMA IF NOT EXISTS ctgov_omop;
+
+-- name: drop_trial_arm
+DROP TABLE IF EXISTS ctgov_omop.trial_arm;
+
+-- name: create_trial_arm
+-- The result groups adverse events are reported for, they take the place of the OMOP person
+CREATE TABLE ctgov_omop.trial_arm AS
+SELECT r.id               AS trial_arm_id,
+       r.nct_id,
+       r.title            AS trial_arm_name,
+       r.ctgov_group_code AS trial_arm_source_value
+FROM ctgov.result_groups r
+WHERE r.ctgov_group_code LIKE 'E%'
+  AND r.nct_id IS NOT NULL;
+
+-- name: drop_drug_exposure
+DROP TABLE IF EXISTS ctgov_omop.drug_exposure;
+
+-- name: create_drug_exposure
+-- One row per arm and ingredient, drug_concept_id is the standard RxNorm ingredient and drug_source_concept_id the
+-- concept that was matched, e.g. a brand name. An ingredient reached from several matched concepts takes the ingredient
+-- itself when it was matched, otherwise the concept with the shortest path. 32809 is the 'Case Report Form' type concept.
+CREATE TABLE ctgov_omop.drug_exposure AS
+WITH exposures AS (SELECT DISTINCT ON (g.id, g.in_rxcui) g.id,
+                                                         g.nct_id,
+                                                         g.in_rxcui,
+                                                         g.rxcui,
+                                                         g.str,
+                                                         g.combination_rxcui
+                   FROM ctgov.result_group_ingredient g
+                   ORDER BY g.id, g.in_rxcui, g.rxcui = g.in_rxcui DESC, length(g.path), g.rxcui, g.str)
+SELECT row_number() OVER (ORDER BY e.id, e.in_rxcui) AS drug_exposure_id,
+       e.id                                         AS trial_arm_id,
+       e.nct_id,
+       coalesce(i.concept_id, 0)                    AS drug_concept_id,
+       32809                                        AS drug_type_concept_id,
+       e.str                                        AS drug_source_value,
+       coalesce(s.concept_id, 0)                    AS drug_source_concept_id,
+       e.combination_rxcui
+FROM exposures e
+         LEFT JOIN omop.concept i
+                   ON i.vocabulary_id = 'RxNorm'
+                       AND i.concept_code = cast(e.in_rxcui AS TEXT)
+         LEFT JOIN omop.concept s
+                   ON s.vocabulary_id = 'RxNorm'
+                       AND s.concept_code = cast(e.rxcui AS TEXT);
+
+-- name: drop_adverse_event
+DROP TABLE IF EXISTS ctgov_omop.adverse_event;
+
+-- name: create_adverse_event
+-- The reported counts per arm and condition, condition_concept_id is the SNOMED CT concept of the preferred term (the
+-- lowest one when there are several) and condition_source_concept_id the MedDRA preferred term. Compound events have a
+-- row per component, unmapped events get concept 0. The snomed pairs are those of create_snomed_map in
+-- snomed_mapping.sql, built here so the export does not depend on a run with the snomed setting.
+CREATE TABLE ctgov_omop.adverse_event AS
+WITH snomed AS (SELECT DISTINCT ON (m.concept_code) cast(m.concept_code AS INTEGER) AS pt_code,
+                                                    s.concept_id                    AS snomed_concept_id
+                FROM omop.concept m
+                         JOIN omop.concept_relationship r
+                              ON r.concept_id_1 = m.concept_id
+                                  AND r.relationship_id = 'MedDRA - SNOMED eq'
+                                  AND r.invalid_reason IS NULL
+                         JOIN omop.concept s
+                              ON s.concept_id = r.concept_id_2
+                                  AND s.vocabulary_id = 'SNOMED'
+                WHERE m.vocabulary_id = 'MedDRA'
+                  AND m.concept_class_id = 'PT'
+                ORDER BY m.concept_code, s.concept_id)
+SELECT row_number() OVER (ORDER BY re.id, p.pt_code) AS adverse_event_id,
+       re.result_group_id                             AS trial_arm_id,
+       re.nct_id,
+       coalesce(sn.snomed_concept_id, 0)              AS condition_concept_id,
+       32809                                          AS condition_type_concept_id,
+       re.adverse_event_term                          AS condition_source_value,
+       coalesce(m.concept_id, 0)                      AS condition_source_concept_id,
+       p.component,
+       re.event_type,
+       re.subjects_affected,
+       re.subjects_at_risk,
+       re.event_count
+FROM ctgov.reported_events_meddra re
+         LEFT JOIN ctgov.reported_event_pt p ON p.reported_event_id = re.id
+         LEFT JOIN omop.concept m
+                   ON m.vocabulary_id = 'MedDRA'
+                       AND m.concept_class_id = 'PT'
+                       AND m.concept_code = cast(p.pt_code AS TEXT)
+         LEFT JOIN snomed sn ON sn.pt_code = p.pt_code
+WHERE re.organ_system IS DISTINCT FROM 'Total'
+  AND re.adverse_event_term IS NOT NULL;
+
+-- name: count_rows
+SELECT (SELECT count(*) FROM ctgov_omop.trial_arm)                                    AS trial_arms,
+       (SELECT count(*) FROM ctgov_omop.drug_exposure)                                AS drug_exposures,
+       (SELECT count(*) FROM ctgov_omop.drug_exposure WHERE drug_concept_id = 0)      AS unknown_drugs,
+       (SELECT count(*) FROM ctgov_omop.adverse_event)                                AS adverse_events,
+       (SELECT count(*) FROM ctgov_omop.adverse_event WHERE condition_concept_id = 0) AS unknown_conditions;
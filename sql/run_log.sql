@@ -0,0 +1,19 @@
+-- name: create_run_log
+CREATE TABLE IF NOT EXISTS ctgov.dracula_run_log
+(
+    run_id TEXT,
+    key    TEXT,
+    value  TEXT
+);
+
+-- name: insert_run_log
+INSERT INTO ctgov.dracula_run_log (run_id, key, value)
+VALUES ($1, $2, $3);
+
+-- name: find_previous_value
+SELECT value
+FROM ctgov.dracula_run_log
+WHERE key = $1
+  AND run_id != $2
+ORDER BY run_id DESC
+LIMIT 1;
@@ -0,0 +1,95 @@
+-- name: find_strata
+-- Per trial, ingredient and preferred term the arms given the ingredient against the placebo arms, arms whose title or
+-- matched design group mentions placebo and that were not given any ingredient
+WITH placebo_arms AS (SELECT DISTINCT r.id
+                      FROM ctgov.result_groups r
+                               LEFT JOIN ctgov.matches m ON m.rg_id = r.id
+                      WHERE r.ctgov_group_code LIKE 'E%'
+                        AND (lower(r.title) LIKE '%placebo%' OR lower(m.dg_title) LIKE '%placebo%')
+                        AND r.id NOT IN (SELECT id FROM ctgov.result_group_ingredient)),
+     treated AS (SELECT nct_id,
+                        cast(in_rxcui AS TEXT)                 AS in_rxcui,
+                        in_str,
+                        pt_code,
+                        pt_name,
+                        cast(sum(subjects_affected) AS BIGINT) AS treated_affected,
+                        cast(sum(subjects_at_risk) AS BIGINT)  AS treated_at_risk
+                 FROM ctgov.ingredient_event_counts
+                 GROUP BY nct_id, in_rxcui, in_str, pt_code, pt_name),
+     control AS (SELECT c.nct_id,
+                        c.pt_code,
+                        cast(sum(c.subjects_affected) AS BIGINT) AS control_affected,
+                        cast(sum(c.subjects_at_risk) AS BIGINT)  AS control_at_risk
+                 FROM ctgov.arm_event_counts c
+                          JOIN placebo_arms p ON p.id = c.result_group_id
+                 GROUP BY c.nct_id, c.pt_code)
+SELECT t.nct_id,
+       t.in_rxcui,
+       t.in_str,
+       t.pt_code,
+       t.pt_name,
+       t.treated_affected,
+       t.treated_at_risk,
+       c.control_affected,
+       c.control_at_risk
+FROM treated t
+         JOIN control c ON c.nct_id = t.nct_id AND c.pt_code = t.pt_code
+ORDER BY t.in_rxcui, t.pt_code, t.nct_id;
+
+-- name: drop_contrast_table
+DROP TABLE IF EXISTS ctgov.ingredient_event_contrasts;
+
+-- name: create_contrast_table
+CREATE TABLE ctgov.ingredient_event_contrasts
+(
+    nct_id                TEXT,
+    in_rxcui              TEXT,
+    pt_code               INTEGER,
+    treated_affected      BIGINT,
+    treated_at_risk       BIGINT,
+    control_affected      BIGINT,
+    control_at_risk       BIGINT,
+    risk_difference       DOUBLE PRECISION,
+    risk_difference_lower DOUBLE PRECISION,
+    risk_difference_upper DOUBLE PRECISION,
+    risk_ratio            DOUBLE PRECISION,
+    risk_ratio_lower      DOUBLE PRECISION,
+    risk_ratio_upper      DOUBLE PRECISION
+);
+
+-- name: insert_contrasts
+INSERT INTO ctgov.ingredient_event_contrasts
+SELECT *
+FROM unnest($1::TEXT[], $2::TEXT[], $3::INT[], $4::BIGINT[], $5::BIGINT[], $6::BIGINT[], $7::BIGINT[],
+            $8::DOUBLE PRECISION[], $9::DOUBLE PRECISION[], $10::DOUBLE PRECISION[], $11::DOUBLE PRECISION[],
+            $12::DOUBLE PRECISION[], $13::DOUBLE PRECISION[]);
+
+-- name: drop_signal_table
+DROP TABLE IF EXISTS ctgov.ingredient_event_signals;
+
+-- name: create_signal_table
+CREATE TABLE ctgov.ingredient_event_signals
+(
+    in_rxcui                 TEXT,
+    in_str                   TEXT,
+    pt_code                  INTEGER,
+    pt_name                  TEXT,
+    trials                   INTEGER,
+    treated_affected         BIGINT,
+    treated_at_risk          BIGINT,
+    control_affected         BIGINT,
+    control_at_risk          BIGINT,
+    mh_risk_difference       DOUBLE PRECISION,
+    mh_risk_difference_lower DOUBLE PRECISION,
+    mh_risk_difference_upper DOUBLE PRECISION,
+    mh_risk_ratio            DOUBLE PRECISION,
+    mh_risk_ratio_lower      DOUBLE PRECISION,
+    mh_risk_ratio_upper      DOUBLE PRECISION
+);
+
+-- name: insert_signals
+INSERT INTO ctgov.ingredient_event_signals
+SELECT *
+FROM unnest($1::TEXT[], $2::TEXT[], $3::INT[], $4::TEXT[], $5::INT[], $6::BIGINT[], $7::BIGINT[], $8::BIGINT[],
+            $9::BIGINT[], $10::DOUBLE PRECISION[], $11::DOUBLE PRECISION[], $12::DOUBLE PRECISION[],
+            $13::DOUBLE PRECISION[], $14::DOUBLE PRECISION[], $15::DOUBLE PRECISION[]);
@@ -0,0 +1,48 @@
+-- name: find_vocabulary_versions
+SELECT lower(vocabulary_id) AS vocabulary_id, vocabulary_version
+FROM omop.vocabulary
+WHERE vocabulary_id IN ('MedDRA', 'SNOMED');
+
+-- name: drop_snomed_map
+DROP TABLE IF EXISTS ctgov.meddra_snomed_map;
+
+-- name: create_snomed_map
+-- Every MedDRA preferred term with its equivalent SNOMED CT concepts, a preferred term can have more than one
+CREATE TABLE ctgov.meddra_snomed_map AS
+SELECT DISTINCT cast(m.concept_code AS INTEGER) AS pt_code,
+                s.concept_id                    AS snomed_concept_id,
+                s.concept_code                  AS snomed_code,
+                s.concept_name                  AS snomed_name
+FROM omop.concept m
+         JOIN omop.concept_relationship r
+              ON r.concept_id_1 = m.concept_id
+                  AND r.relationship_id = 'MedDRA - SNOMED eq'
+                  AND r.invalid_reason IS NULL
+         JOIN omop.concept s
+              ON s.concept_id = r.concept_id_2
+                  AND s.vocabulary_id = 'SNOMED'
+WHERE m.vocabulary_id = 'MedDRA'
+  AND m.concept_class_id = 'PT';
+
+-- name: drop_event_snomed_table
+DROP TABLE IF EXISTS ctgov.reported_event_snomed;
+
+-- name: create_event_snomed_table
+-- One row per reported event, preferred term and SNOMED CT concept, compound events have rows for every component
+CREATE TABLE ctgov.reported_event_snomed AS
+SELECT p.reported_event_id,
+       p.pt_code,
+       p.component,
+       s.snomed_concept_id,
+       s.snomed_code,
+       s.snomed_name
+FROM ctgov.reported_event_pt p
+         JOIN ctgov.meddra_snomed_map s ON s.pt_code = p.pt_code;
+
+-- name: event_snomed_index
+CREATE INDEX reported_event_snomed_event_index
+    ON ctgov.reported_event_snomed (reported_event_id);
+
+-- name: count_snomed_events
+SELECT (SELECT count(DISTINCT reported_event_id) FROM ctgov.reported_event_snomed) AS mapped,
+       (SELECT count(DISTINCT reported_event_id) FROM ctgov.reported_event_pt)     AS total;
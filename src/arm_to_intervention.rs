@@ -4,18 +4,17 @@ use std::error::Error;
 use deadpool_postgres::tokio_postgres::Row;
 use deadpool_postgres::Pool;
 use log::info;
-use pbr::ProgressBar;
 use rawsql::Loader;
 
-use crate::db::execute;
+use crate::db::{execute, execute_params};
+use crate::drug_lists::DrugLists;
+use crate::progress::Progress;
 
-pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Error>> {
-    let cumulative_groups_names = vec![
-        String::from("total"),
-        String::from("all@patients"),
-        String::from("overall participants"),
-        String::from("all participants"),
-    ];
+pub async fn connect_arms_to_interventions(
+    pool: &Pool,
+    lists: &DrugLists,
+) -> Result<(), Box<dyn Error>> {
+    let cumulative_groups_names = &lists.cumulative_group_names;
 
     let client = pool.get().await?;
     let queries = Loader::read_queries_from("./sql/arms_to_interventions.sql").unwrap();
@@ -29,8 +28,7 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
     let result = client.query(q.as_str(), &[]).await?;
     let total_trials = result.len();
     info!("Processing {} Clinical Trials", total_trials);
-    let mut pb = ProgressBar::new((total_trials / 1000) as u64);
-    pb.set_width(Some(80));
+    let progress = Progress::new(total_trials);
 
     let studies: Vec<(String, Option<String>)> = result
         .iter()
@@ -39,23 +37,18 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
 
     let q = queries.get("match").unwrap();
     let stmt = q.as_str();
-    let mut counter: u16 = 0;
 
     for study_tup in studies {
-        counter += 1;
-        if counter % 1000 == 0 {
-            pb.inc();
-        }
+        progress.inc();
         let study_id = study_tup.0;
         let study_model = study_tup.1.unwrap_or(String::from("Unkown"));
 
         let q = queries.get("find_result_groups").unwrap();
         let result = client.query(q.as_str(), &[&study_id]).await?;
-        let result_groups: Vec<Group>;
-        if result.len() > 1 {
-            result_groups = result
+        let result_groups: Vec<Group> = if result.len() > 1 {
+            result
                 .iter()
-                .map(|r| Group::from(r))
+                .map(Group::from)
                 .filter(|rg| {
                     !cumulative_groups_names.contains(
                         &rg.title
@@ -64,25 +57,25 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
                             .to_lowercase(),
                     )
                 })
-                .collect();
+                .collect()
         } else {
-            result_groups = result.iter().map(|r| Group::from(r)).collect();
-        }
+            result.iter().map(Group::from).collect()
+        };
 
         if study_model.eq_ignore_ascii_case("single group assignment") {
-            attach_all_interventions(&pool, &queries, &study_id, &result_groups).await
+            attach_all_interventions(pool, &queries, &study_id, &result_groups).await
         }
 
         let q = queries.get("find_design_groups").unwrap();
         let result = client.query(q.as_str(), &[&study_id]).await?;
-        let design_groups: Vec<Group> = result.iter().map(|r| Group::from(r)).collect();
+        let design_groups: Vec<Group> = result.iter().map(Group::from).collect();
 
-        if design_groups.len() == 0 {
-            no_design_group(&pool, &queries, &stmt, &study_id, &result_groups)
+        if design_groups.is_empty() {
+            no_design_group(pool, &queries, stmt, &study_id, &result_groups)
                 .await
                 .unwrap();
         } else if design_groups.len() == 1 {
-            let dg = design_groups.get(0).unwrap();
+            let dg = design_groups.first().unwrap();
             for rg in result_groups {
                 if !rg
                     .title
@@ -90,15 +83,7 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
                     .unwrap_or(&String::from("no title"))
                     .contains("placebo")
                 {
-                    update(
-                        &pool,
-                        &stmt,
-                        &study_id,
-                        &&rg,
-                        &dg,
-                        String::from("only one dg"),
-                    )
-                    .await;
+                    update(pool, stmt, &study_id, &&rg, dg, String::from("only one dg")).await;
                 } else {
                     let dg = Group {
                         id: None,
@@ -107,8 +92,8 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
                         intervention: None,
                     };
                     update(
-                        &pool,
-                        &stmt,
+                        pool,
+                        stmt,
                         &study_id,
                         &&rg,
                         &dg,
@@ -118,7 +103,7 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
                 }
             }
         } else if result_groups.len() == design_groups.len() {
-            rg_and_dg_of_equal_len(&pool, &stmt, &study_id, &design_groups, &result_groups).await?;
+            rg_and_dg_of_equal_len(pool, stmt, &study_id, &design_groups, &result_groups).await?;
         } else if result_groups.len() < design_groups.len() {
             for rg in &result_groups {
                 for dg in &design_groups {
@@ -134,11 +119,11 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
                         )
                     {
                         update(
-                            &pool,
-                            &stmt,
+                            pool,
+                            stmt,
                             &study_id,
                             &rg,
-                            &dg,
+                            dg,
                             String::from("direct hit fewer result groups"),
                         )
                         .await;
@@ -179,8 +164,8 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
                             .replace("group", "")
                             .replace("ii", "2");
                         let placebo = "placebo";
-                        if (dg_title.contains(&placebo) && !rg_title.contains(&placebo))
-                            || (rg_title.contains(&placebo)) && !dg_title.contains(&placebo)
+                        if (dg_title.contains(placebo) && !rg_title.contains(placebo))
+                            || (rg_title.contains(placebo)) && !dg_title.contains(placebo)
                         {
                             continue;
                         }
@@ -195,11 +180,11 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
                     if !comparsions.is_empty() {
                         let dg = comparsions.iter().next().unwrap().1;
                         update(
-                            &pool,
-                            &stmt,
+                            pool,
+                            stmt,
                             &study_id,
                             &rg,
-                            &dg,
+                            dg,
                             String::from("pattern match one to many"),
                         )
                         .await;
@@ -208,9 +193,14 @@ pub async fn connect_arms_to_interventions(pool: &Pool) -> Result<(), Box<dyn Er
             }
         }
     }
-    pb.finish();
-    println!();
-    execute("populate_remaining", &client, &queries).await;
+    progress.finish();
+    execute_params(
+        "populate_remaining",
+        &client,
+        &queries,
+        &[cumulative_groups_names],
+    )
+    .await;
     Ok(())
 }
 
@@ -245,22 +235,14 @@ async fn rg_and_dg_of_equal_len(
                                 id: 0,
                                 name: String::from(""),
                             }])
-                            .get(0)
+                            .first()
                             .unwrap()
                             .name
                             .as_str(),
                     )
             {
                 found = true;
-                update(
-                    &pool,
-                    &stmt,
-                    &study_id,
-                    &rg,
-                    &dg,
-                    String::from("direct hit"),
-                )
-                .await;
+                update(pool, stmt, study_id, &rg, dg, String::from("direct hit")).await;
             }
         }
         if !found {
@@ -297,9 +279,9 @@ async fn rg_and_dg_of_equal_len(
             if matches == 1 {
                 found = true;
                 update(
-                    &pool,
-                    &stmt,
-                    &study_id,
+                    pool,
+                    stmt,
+                    study_id,
                     &rg,
                     matching_dg.unwrap(),
                     String::from("direct hit"),
@@ -345,15 +327,7 @@ async fn rg_and_dg_of_equal_len(
             }
             if !comparsions.is_empty() {
                 let dg = comparsions.iter().next().unwrap().1;
-                update(
-                    &pool,
-                    &stmt,
-                    &study_id,
-                    &rg,
-                    &dg,
-                    String::from("pattern match"),
-                )
-                .await;
+                update(pool, stmt, study_id, &rg, dg, String::from("pattern match")).await;
             }
         }
     }
@@ -394,7 +368,7 @@ async fn no_design_group(
     result_groups: &Vec<Group>,
 ) -> Result<(), Box<dyn Error>> {
     let client = pool.get().await.unwrap();
-    let rg = result_groups.get(0).unwrap();
+    let rg = result_groups.first().unwrap();
     let dg = Group {
         id: None,
         title: None,
@@ -402,9 +376,9 @@ async fn no_design_group(
         intervention: None,
     };
     update(
-        &pool,
-        &stmt,
-        &study,
+        pool,
+        stmt,
+        study,
         &rg,
         &dg,
         String::from("no design groups"),
@@ -412,7 +386,7 @@ async fn no_design_group(
     .await;
 
     if result_groups.len() == 1 {
-        attach_all_interventions(&pool, &queries, &study, &result_groups).await;
+        attach_all_interventions(pool, queries, study, result_groups).await;
         return Ok(());
     }
     let q = queries.get("find_interventions").unwrap();
@@ -423,7 +397,7 @@ async fn no_design_group(
     if result_groups.len() > 1 {
         for rg in result_groups {
             let mut found = false;
-            let rg_id = rg.id.clone().unwrap_or(0);
+            let rg_id = rg.id.unwrap_or(0);
             let title = rg.title.clone().unwrap_or(String::from(""));
             let descr = rg.description.clone().unwrap_or(String::from(""));
             let placebo = String::from("placebo");
@@ -441,17 +415,15 @@ async fn no_design_group(
                         .unwrap();
                 }
             }
-            if !found {
-                if interventions.len() == 1 && !title.contains(&placebo) {
-                    let i_id: i32 = interventions.get(0).unwrap().get("id");
-                    client
-                        .execute(
-                            "INSERT INTO ctgov.result_group_intervention VALUES($1,$2)",
-                            &[&rg_id, &i_id],
-                        )
-                        .await
-                        .unwrap();
-                }
+            if !found && interventions.len() == 1 && !title.contains(&placebo) {
+                let i_id: i32 = interventions.first().unwrap().get("id");
+                client
+                    .execute(
+                        "INSERT INTO ctgov.result_group_intervention VALUES($1,$2)",
+                        &[&rg_id, &i_id],
+                    )
+                    .await
+                    .unwrap();
             }
         }
     }
@@ -482,7 +454,7 @@ async fn update(pool: &Pool, stmt: &str, study: &String, rg: &&Group, dg: &Group
             let mut insert_values: String = String::from("");
             for i in ins {
                 let value = format!("({},{}),", rg.id.unwrap(), i.id);
-                insert_values.push_str(&*value);
+                insert_values.push_str(&value);
             }
             // removing trailing comma
             insert_values.pop();
@@ -506,7 +478,7 @@ impl From<&str> for Intervention {
         let parts: Vec<&str> = str.split(" || ").collect();
         Self {
             id: parts.get(1).unwrap_or(&"0").parse().unwrap_or(0),
-            name: parts.get(0).unwrap_or(&"").to_string(),
+            name: parts.first().unwrap_or(&"").to_string(),
         }
     }
 }
@@ -526,11 +498,11 @@ impl From<&Row> for Group {
             Some(o) => {
                 let i: Vec<&str> = o.split(" ||| ").collect();
                 // Due to the way we query for the interventions we need to do this 'empty' check
-                let check: Vec<&str> = i.get(0).unwrap().split(" || ").collect();
-                if check.get(0).unwrap().is_empty() {
+                let check: Vec<&str> = i.first().unwrap().split(" || ").collect();
+                if check.first().unwrap().is_empty() {
                     None
                 } else {
-                    let interventions = i.iter().map(|i| Intervention::from(i.clone())).collect();
+                    let interventions = i.iter().map(|i| Intervention::from(*i)).collect();
                     Some(interventions)
                 }
             }
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+/// A BK-tree over strings using the Damerau-Levenshtein distance, it finds all strings within a
+/// maximum distance of a query without comparing the query to every string.
+#[derive(Default)]
+pub struct BkTree {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    word: String,
+    children: HashMap<usize, usize>,
+}
+
+impl BkTree {
+    pub fn insert(&mut self, word: &str) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node::new(word));
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = distance::damerau_levenshtein(&self.nodes[current].word, word);
+            if distance == 0 {
+                return;
+            }
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push(Node::new(word));
+                    self.nodes[current].children.insert(distance, index);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// All words within max_distance of the query together with their distance
+    pub fn find(&self, query: &str, max_distance: usize) -> Vec<(&str, usize)> {
+        let mut found = Vec::new();
+        if self.nodes.is_empty() {
+            return found;
+        }
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let distance = distance::damerau_levenshtein(&node.word, query);
+            if distance <= max_distance {
+                found.push((node.word.as_str(), distance));
+            }
+            let low = distance.saturating_sub(max_distance);
+            let high = distance + max_distance;
+            for (d, child) in &node.children {
+                if *d >= low && *d <= high {
+                    stack.push(*child);
+                }
+            }
+        }
+        found
+    }
+}
+
+impl Node {
+    fn new(word: &str) -> Self {
+        Self {
+            word: word.to_string(),
+            children: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(words: &[&str]) -> BkTree {
+        let mut tree = BkTree::default();
+        for word in words {
+            tree.insert(word);
+        }
+        tree
+    }
+
+    #[test]
+    fn finds_misspellings() {
+        let tree = tree(&["metformin", "atorvastatin", "simvastatin", "insulin"]);
+        assert_eq!(tree.find("metformine", 1), vec![("metformin", 1)]);
+        assert_eq!(tree.find("atorvastatine", 1), vec![("atorvastatin", 1)]);
+        assert!(tree.find("paracetamol", 2).is_empty());
+    }
+
+    #[test]
+    fn finds_all_words_within_distance() {
+        let tree = tree(&["book", "books", "cake", "boo", "cape", "cart"]);
+        let mut found: Vec<&str> = tree.find("bool", 1).into_iter().map(|f| f.0).collect();
+        found.sort();
+        assert_eq!(found, vec!["boo", "book"]);
+    }
+
+    #[test]
+    fn empty_tree() {
+        assert!(BkTree::default().find("anything", 3).is_empty());
+    }
+}
@@ -0,0 +1,21 @@
+use std::error::Error;
+
+use deadpool_postgres::Pool;
+use log::info;
+use rawsql::Loader;
+
+use crate::db::execute;
+
+/// Counts the subjects affected and at risk per arm and preferred term in ctgov.arm_event_counts,
+/// and per arm, ingredient and preferred term in ctgov.ingredient_event_counts
+pub async fn count_events(pool: &Pool) -> Result<(), Box<dyn Error>> {
+    info!("Counting the affected subjects per arm, ingredient and preferred term");
+    let client = pool.get().await?;
+    let queries = Loader::read_queries_from("./sql/contingency.sql").unwrap();
+    execute("drop_arm_counts", &client, &queries).await;
+    execute("create_arm_counts", &client, &queries).await;
+    execute("drop_ingredient_counts", &client, &queries).await;
+    execute("create_ingredient_counts", &client, &queries).await;
+    execute("ingredient_counts_index", &client, &queries).await;
+    Ok(())
+}
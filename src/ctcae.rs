@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fs;
+
+use log::info;
+
+use crate::normalize::Normalizer;
+
+/// Words naming tumours, whose grades (e.g. 'grade ii astrocytoma') are histological and not CTCAE
+const TUMOUR_WORDS: [&str; 14] = [
+    "astrocytoma",
+    "glioma",
+    "lymphoma",
+    "carcinoma",
+    "sarcoma",
+    "meningioma",
+    "ependymoma",
+    "blastoma",
+    "tumour",
+    "tumor",
+    "neoplasm",
+    "neoplasia",
+    "cancer",
+    "dysplasia",
+];
+
+/// Splits a CTCAE style grade off a lowercased adverse event term, e.g. 'neutropenia grade 3'
+/// gives ('neutropenia', 3). Recognizes 'grade 3', 'gr 3', 'g3', 'grade3' and roman numerals,
+/// for ranges such as 'grade 3-4' the lowest grade is returned. Only the grade is removed, the
+/// rest of the term keeps its punctuation. Terms naming a tumour are left alone.
+pub fn parse_grade(term: &str) -> (String, Option<i16>) {
+    let words = split_words(term);
+    if words
+        .iter()
+        .any(|(_, _, word)| TUMOUR_WORDS.iter().any(|t| word.ends_with(t)))
+    {
+        return (term.to_string(), None);
+    }
+    for (i, (start, end, word)) in words.iter().enumerate() {
+        let next = words.get(i + 1).map(|w| w.2).unwrap_or("");
+        let (grade, end) = match *word {
+            "grade" | "gr" | "gr." => match grade(next) {
+                Some(grade) => (grade, words[i + 1].1),
+                None => continue,
+            },
+            "g" => match numeric_grade(next) {
+                Some(grade) => (grade, words[i + 1].1),
+                None => continue,
+            },
+            _ => match ["grade", "gr", "g"]
+                .iter()
+                .find_map(|prefix| word.strip_prefix(prefix).and_then(numeric_grade))
+            {
+                Some(grade) => (grade, *end),
+                None => continue,
+            },
+        };
+        let rest = format!("{} {}", &term[..*start], &term[end..]);
+        return (tidy(&rest), Some(grade));
+    }
+    (term.to_string(), None)
+}
+
+/// The words of a term with their byte offsets, brackets and separators split words
+fn split_words(term: &str) -> Vec<(usize, usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in term.char_indices().chain([(term.len(), ' ')]) {
+        if c.is_whitespace() || "(),:;[]".contains(c) {
+            if let Some(s) = start.take() {
+                words.push((s, i, &term[s..i]));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    words
+}
+
+/// Collapses the spaces and drops the brackets and separators left empty by removing a grade
+fn tidy(term: &str) -> String {
+    let mut tidy = term.split_whitespace().collect::<Vec<_>>().join(" ");
+    for empty in ["( )", "()", "[ ]", "[]"] {
+        tidy = tidy.replace(empty, "");
+    }
+    tidy.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(" ,", ",")
+        .trim_matches(|c: char| c.is_whitespace() || ",:;-".contains(c))
+        .to_string()
+}
+
+/// Reads a grade that starts with a digit, so words such as 'gi' are not taken for 'g' plus 'i'
+fn numeric_grade(word: &str) -> Option<i16> {
+    if word.starts_with(|c: char| c.is_ascii_digit()) {
+        grade(word)
+    } else {
+        None
+    }
+}
+
+/// Reads a grade such as '3', 'iii', '3-4', '3/4' or '3+'
+fn grade(word: &str) -> Option<i16> {
+    let first = word.split(['-', '/', '+']).next()?;
+    let grade = match first {
+        "i" => 1,
+        "ii" => 2,
+        "iii" => 3,
+        "iv" => 4,
+        "v" => 5,
+        _ => first.parse().ok()?,
+    };
+    (1..=5).contains(&grade).then_some(grade)
+}
+
+/// Reads the CTCAE to MedDRA correspondence, a tab separated file with a header and the columns
+/// ctcae_term and meddra_code, the MedDRA lowest level term code. The terms are normalized.
+pub fn read_correspondence(path: &str, normalizer: &Normalizer) -> HashMap<String, i32> {
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Error reading CTCAE correspondence {}: {}", path, e));
+    let correspondence: HashMap<String, i32> = content
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (term, code) = line
+                .split_once('\t')
+                .unwrap_or_else(|| panic!("Invalid line in {}: {:?}", path, line));
+            let code = code
+                .trim()
+                .parse()
+                .unwrap_or_else(|e| panic!("Invalid MedDRA code in {}: {}", path, e));
+            (normalizer.normalize(term), code)
+        })
+        .collect();
+    info!("Read {} CTCAE terms from {}", correspondence.len(), path);
+    correspondence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_grades() {
+        assert_eq!(
+            parse_grade("neutropenia grade 3"),
+            (String::from("neutropenia"), Some(3))
+        );
+        assert_eq!(
+            parse_grade("anemia (grade iii)"),
+            (String::from("anemia"), Some(3))
+        );
+        assert_eq!(
+            parse_grade("grade 3-4 diarrhea"),
+            (String::from("diarrhea"), Some(3))
+        );
+        assert_eq!(
+            parse_grade("febrile neutropenia g4"),
+            (String::from("febrile neutropenia"), Some(4))
+        );
+        assert_eq!(parse_grade("rash, grade2"), (String::from("rash"), Some(2)));
+    }
+
+    #[test]
+    fn only_removes_the_grade() {
+        assert_eq!(
+            parse_grade("infection (viral), grade 2"),
+            (String::from("infection (viral)"), Some(2))
+        );
+        assert_eq!(
+            parse_grade("nausea/vomiting grade 1"),
+            (String::from("nausea/vomiting"), Some(1))
+        );
+        assert_eq!(
+            parse_grade("rash: maculo-papular [grade 3]"),
+            (String::from("rash: maculo-papular"), Some(3))
+        );
+    }
+
+    #[test]
+    fn leaves_meddra_names_with_grades_alone() {
+        let follicular = "follicle centre lymphoma, follicular grade i, ii, iii stage iv";
+        assert_eq!(parse_grade(follicular), (String::from(follicular), None));
+        assert_eq!(
+            parse_grade("grade ii astrocytoma"),
+            (String::from("grade ii astrocytoma"), None)
+        );
+        assert_eq!(
+            parse_grade("low grade fever"),
+            (String::from("low grade fever"), None)
+        );
+    }
+
+    #[test]
+    fn leaves_other_terms_alone() {
+        assert_eq!(parse_grade("nausea"), (String::from("nausea"), None));
+        assert_eq!(parse_grade("vitamin g"), (String::from("vitamin g"), None));
+        assert_eq!(parse_grade("gout"), (String::from("gout"), None));
+        assert_eq!(
+            parse_grade("gi bleeding"),
+            (String::from("gi bleeding"), None)
+        );
+        assert_eq!(
+            parse_grade("grade 7 pain"),
+            (String::from("grade 7 pain"), None)
+        );
+    }
+}
@@ -12,31 +12,39 @@ use tokio_postgres::NoTls;
 pub fn init_db_pool(config: &Config) -> Pool {
     let mut pg_config = tokio_postgres::Config::new();
     pg_config.port(config.get_int("pg_port").unwrap() as u16);
-    pg_config.host(&*config.get_string("pg_host").unwrap());
-    pg_config.user(&*config.get_string("pg_user").unwrap());
-    pg_config.dbname(&*config.get_string("pg_dbname").unwrap());
-    pg_config.password(&*config.get_string("pg_password").unwrap());
+    pg_config.host(config.get_string("pg_host").unwrap());
+    pg_config.user(config.get_string("pg_user").unwrap());
+    pg_config.dbname(config.get_string("pg_dbname").unwrap());
+    pg_config.password(config.get_string("pg_password").unwrap());
     let mgr_config = ManagerConfig {
         recycling_method: RecyclingMethod::Fast,
     };
     let mgr: Manager = Manager::from_config(pg_config, NoTls, mgr_config);
-    let pool: Pool = Pool::builder(mgr).max_size(6).build().unwrap();
-    pool
+    Pool::builder(mgr).max_size(6).build().unwrap()
 }
 
 pub async fn execute(
     query_name: &str,
     client: &Object<Manager>,
     queries: &HashMap<String, String>,
+) {
+    execute_params(query_name, client, queries, &[]).await;
+}
+
+pub async fn execute_params(
+    query_name: &str,
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    params: &[&(dyn ToSql + Sync)],
 ) {
     let start = Instant::now();
 
     debug!("Executing the {} query... ", query_name);
 
     let result = client
-        .execute(queries.get(query_name).unwrap().as_str(), &[])
+        .execute(queries.get(query_name).unwrap().as_str(), params)
         .await
-        .expect(&msg(query_name));
+        .unwrap_or_else(|e| panic!("{}: {}", msg(query_name), e));
 
     let seconds = start.elapsed().as_secs_f32();
 
@@ -59,7 +67,7 @@ pub async fn query(
     let result = client
         .query(queries.get(query_name).unwrap().as_str(), params)
         .await
-        .expect(&msg(query_name));
+        .unwrap_or_else(|e| panic!("{}: {}", msg(query_name), e));
 
     let seconds = start.elapsed().as_secs_f32();
 
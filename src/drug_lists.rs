@@ -0,0 +1,173 @@
+use config::{Config, File, FileFormat, Source};
+use deadpool_postgres::Pool;
+use log::info;
+use rawsql::Loader;
+
+use crate::db::{execute, execute_params};
+use crate::normalize::Normalizer;
+
+/// Stop word, junk and exclusion lists used while mapping drugs. The lists are read from the
+/// files named in the `drug_lists` setting, every file adds to the lists of the previous ones.
+#[derive(Debug, Clone)]
+pub struct DrugLists {
+    /// The files the lists were read from together with their version
+    pub sources: Vec<String>,
+    /// Intervention and group names that never refer to a drug
+    pub junk: Vec<String>,
+    /// Names that are rewritten to 'placebo'
+    pub placebo: Vec<String>,
+    /// First words that turn the whole name into 'placebo', e.g. 'sham'
+    pub placebo_prefixes: Vec<String>,
+    /// RxNorm concepts that are never attached to a result group
+    pub excluded_rxcuis: Vec<i32>,
+    /// Result group titles of groups that combine all other groups
+    pub cumulative_group_names: Vec<String>,
+}
+
+/// The default lists, compiled in so the binary and resources/drug_lists.toml cannot disagree
+const BUNDLED_LISTS: &str = include_str!("../resources/drug_lists.toml");
+
+impl Default for DrugLists {
+    fn default() -> Self {
+        let mut lists = Self::empty();
+        lists.add_source(File::from_str(BUNDLED_LISTS, FileFormat::Toml), "built-in");
+        lists
+    }
+}
+
+impl DrugLists {
+    pub fn from_settings(settings: &Config) -> Self {
+        let paths = match settings.get_array("drug_lists") {
+            Ok(paths) => paths,
+            Err(_) => {
+                info!("No drug_lists in the settings file, using the built-in lists");
+                return Self::default();
+            }
+        };
+        let mut lists = Self::empty();
+        for path in paths {
+            let path = path
+                .into_string()
+                .expect("drug_lists must be a list of file names");
+            info!("Reading drug lists from {}", path);
+            lists.add_source(File::with_name(&path), &path);
+        }
+        lists
+    }
+
+    fn empty() -> Self {
+        Self {
+            sources: vec![],
+            junk: vec![],
+            placebo: vec![],
+            placebo_prefixes: vec![],
+            excluded_rxcuis: vec![],
+            cumulative_group_names: vec![],
+        }
+    }
+
+    fn add_source<T: Source + Send + Sync + 'static>(&mut self, source: T, path: &str) {
+        let file = Config::builder()
+            .add_source(source)
+            .build()
+            .unwrap_or_else(|e| panic!("Error reading drug lists {}: {}", path, e));
+        let version = file
+            .get_string("version")
+            .unwrap_or_else(|_| panic!("{} does not have a version", path));
+        self.sources.push(format!("{}@{}", path, version));
+
+        extend(&mut self.junk, strings(&file, "junk"));
+        extend(&mut self.placebo, strings(&file, "placebo"));
+        extend(
+            &mut self.placebo_prefixes,
+            strings(&file, "placebo_prefixes"),
+        );
+        extend(
+            &mut self.cumulative_group_names,
+            strings(&file, "cumulative_group_names"),
+        );
+        let rxcuis = file
+            .get_array("excluded_rxcuis")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| {
+                v.into_int()
+                    .unwrap_or_else(|e| panic!("Invalid rxcui in {}: {}", path, e))
+                    as i32
+            })
+            .collect();
+        extend(&mut self.excluded_rxcuis, rxcuis);
+    }
+
+    /// The files and versions the lists were read from, to be recorded with the run
+    pub fn version(&self) -> String {
+        self.sources.join(", ")
+    }
+
+    /// Normalizes the names that are compared with normalized interventions and group titles
+    pub fn normalize(mut self, normalizer: &Normalizer) -> Self {
+        self.junk = self.junk.iter().map(|t| normalizer.normalize(t)).collect();
+        self.placebo = self
+            .placebo
+            .iter()
+            .map(|t| normalizer.normalize(t))
+            .collect();
+        self.placebo_prefixes = self
+            .placebo_prefixes
+            .iter()
+            .map(|t| normalizer.normalize(t))
+            .collect();
+        self
+    }
+
+    /// Stores the excluded rxcuis in ctgov.excluded_rxcuis so the mapping queries can use them
+    pub async fn store(&self, pool: &Pool) {
+        let queries = Loader::read_queries_from("./sql/drug_lists.sql").unwrap();
+        let client = pool.get().await.unwrap();
+        execute("drop_excluded_rxcuis", &client, &queries).await;
+        execute("create_excluded_rxcuis", &client, &queries).await;
+        execute_params(
+            "insert_excluded_rxcuis",
+            &client,
+            &queries,
+            &[&self.excluded_rxcuis],
+        )
+        .await;
+    }
+}
+
+fn strings(file: &Config, key: &str) -> Vec<String> {
+    file.get_array(key)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| {
+            v.into_string()
+                .unwrap_or_else(|e| panic!("Invalid value in {}: {}", key, e))
+        })
+        .collect()
+}
+
+fn extend<T: PartialEq>(list: &mut Vec<T>, values: Vec<T>) {
+    for value in values {
+        if !list.contains(&value) {
+            list.push(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_bundled_lists() {
+        let lists = DrugLists::default();
+        assert_eq!(lists.version(), "built-in@1");
+        assert!(lists.junk.contains(&String::from("usual care")));
+        assert_eq!(lists.placebo_prefixes, vec![String::from("sham")]);
+        assert!(lists.excluded_rxcuis.contains(&1001007));
+        assert!(lists
+            .cumulative_group_names
+            .contains(&String::from("all@patients")));
+    }
+}
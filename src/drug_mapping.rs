@@ -1,21 +1,52 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::error::Error;
 
+use config::Config;
+use deadpool::managed::Object;
 use deadpool_postgres::tokio_postgres::Row;
-use deadpool_postgres::Pool;
+use deadpool_postgres::{Manager, Pool};
 use log::{debug, info, warn};
-use pbr::ProgressBar;
 use rawsql::Loader;
 
-use crate::db::execute;
+use crate::bk_tree::BkTree;
+use crate::db::{execute, execute_params, query};
+use crate::drug_lists::DrugLists;
+use crate::normalize::{normalize_column, Normalizer};
+use crate::progress::Progress;
+use crate::regimens::Regimen;
+
+/// Settings for the fuzzy fallback that matches misspelled words to RxNorm ingredients and brands
+pub struct FuzzyMatching {
+    /// The maximum Damerau-Levenshtein distance, 0 turns the fuzzy matching off
+    pub max_distance: usize,
+    /// Words shorter than this are never matched fuzzily
+    pub min_length: usize,
+}
+
+impl FuzzyMatching {
+    pub fn from_settings(settings: &Config) -> Self {
+        Self {
+            max_distance: settings.get_int("fuzzy_drug_distance").unwrap_or(1) as usize,
+            min_length: settings.get_int("fuzzy_drug_min_length").unwrap_or(6) as usize,
+        }
+    }
+}
 
-pub async fn find_drugs(pool: &Pool) -> Result<(), Box<dyn Error>> {
+pub async fn find_drugs(
+    pool: &Pool,
+    normalizer: &Normalizer,
+    lists: &DrugLists,
+    regimens: &[Regimen],
+    fuzzy: &FuzzyMatching,
+    exclude_ambiguous_brands: bool,
+) -> Result<(), Box<dyn Error>> {
     let queries = Loader::read_queries_from("./sql/drug_mapping.sql").unwrap();
 
     let client = pool.get().await?;
 
-    load_art57(&pool).await;
+    load_art57(pool, &queries, normalizer).await;
+    load_rxnorm_terms(pool, &queries, normalizer).await;
 
     info!("Creating tables");
     execute("drop_mapping_table", &client, &queries).await;
@@ -23,56 +54,111 @@ pub async fn find_drugs(pool: &Pool) -> Result<(), Box<dyn Error>> {
     execute("create_mapping_table", &client, &queries).await;
     execute("mapping_clean_index", &client, &queries).await;
     execute("create_join_table", &client, &queries).await;
-    execute("remove_junk", &client, &queries).await;
+    remove_junk(&client, &queries, lists).await;
 
-    direct_match(&queries, &pool).await;
+    raw_match(&queries, pool).await;
 
-    info!("Regex cleaning of data");
-    execute("remove_q2w", &client, &queries).await;
-    execute("remove_non_alpha_numeric", &client, &queries).await;
-    execute("sham_to_placebo", &client, &queries).await;
-    execute("remove_qd", &client, &queries).await;
-    execute("remove_group", &client, &queries).await;
-    execute("remove_cohort", &client, &queries).await;
-    execute("remove_arm", &client, &queries).await;
-    execute("remove_spaces", &client, &queries).await;
-    execute("trim", &client, &queries).await;
-    execute("remove_junk", &client, &queries).await;
+    info!("Normalizing interventions and group titles");
+    clean_terms(&client, &queries, normalizer).await;
+    sham_to_placebo(&client, &queries, lists).await;
+    remove_junk(&client, &queries, lists).await;
 
-    direct_match(&queries, &pool).await;
+    direct_match(&queries, pool).await;
 
     execute("pre_and_append_spaces", &client, &queries).await;
 
+    if !regimens.is_empty() {
+        expand_regimens(pool, regimens, &queries).await;
+    }
+
     let ttys = get_ttys();
     for tty in ttys {
         info!(
             "Going to check if titles or names contain any RxNorm {}",
             tty.1
         );
-        match_words(&pool, &tty.0, &queries).await;
+        match_words(pool, tty.0, &queries).await;
     }
 
-    execute("remove_spaces", &client, &queries).await;
-    execute("trim", &client, &queries).await;
-    execute("remove_junk", &client, &queries).await;
+    clean_terms(&client, &queries, normalizer).await;
+    remove_junk(&client, &queries, lists).await;
 
-    direct_match(&queries, &pool).await;
+    direct_match(&queries, pool).await;
 
-    info!("Creating join tables for mapped rxcuis");
-    execute("drop_join_table_1", &client, &queries).await;
-    execute("drop_join_table_2", &client, &queries).await;
-    execute("drop_join_table_3", &client, &queries).await;
-    execute("drop_join_table_4", &client, &queries).await;
+    if fuzzy.max_distance > 0 {
+        fuzzy_match(pool, fuzzy, &queries).await;
+    }
 
-    execute("join_table_1", &client, &queries).await;
-    execute("join_table_2", &client, &queries).await;
-    execute("join_table_3", &client, &queries).await;
-    execute("join_table_4", &client, &queries).await;
+    create_join_tables(&client, &queries).await;
+
+    info!("Creating the brand name resolution report");
+    execute("drop_ingredient_paths", &client, &queries).await;
+    execute("create_ingredient_paths", &client, &queries).await;
+    execute("drop_brand_resolution", &client, &queries).await;
+    execute("create_brand_resolution", &client, &queries).await;
+    if exclude_ambiguous_brands {
+        info!("Removing matches to brand names with more than one set of ingredients");
+        execute("remove_ambiguous_brands", &client, &queries).await;
+        create_join_tables(&client, &queries).await;
+    }
 
     Ok(())
 }
 
-async fn load_art57(pool: &Pool) {
+async fn create_join_tables(client: &Object<Manager>, queries: &HashMap<String, String>) {
+    info!("Creating join tables for mapped rxcuis");
+    execute("drop_join_table_1", client, queries).await;
+    execute("drop_join_table_2", client, queries).await;
+    execute("drop_join_table_3", client, queries).await;
+    execute("drop_join_table_4", client, queries).await;
+
+    execute("join_table_1", client, queries).await;
+    execute("join_table_2", client, queries).await;
+    execute("join_table_3", client, queries).await;
+    execute("join_table_4", client, queries).await;
+}
+
+/// Normalizes the clean column of the drug_mapping table, shared with the ChEMBL mapping
+pub async fn clean_terms(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    normalizer: &Normalizer,
+) {
+    normalize_column::<i64>(
+        "find_terms_to_clean",
+        "update_clean",
+        client,
+        queries,
+        normalizer,
+    )
+    .await;
+}
+
+/// Removes the names that never refer to a drug, shared with the ChEMBL mapping
+pub async fn remove_junk(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    lists: &DrugLists,
+) {
+    execute_params("remove_junk", client, queries, &[&lists.junk]).await;
+}
+
+/// Rewrites all names for placebo to 'placebo', shared with the ChEMBL mapping
+pub async fn sham_to_placebo(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    lists: &DrugLists,
+) {
+    execute_params(
+        "sham_to_placebo",
+        client,
+        queries,
+        &[&lists.placebo, &lists.placebo_prefixes],
+    )
+    .await;
+}
+
+async fn load_art57(pool: &Pool, queries: &HashMap<String, String>, normalizer: &Normalizer) {
     info!("Loading article 57 data");
     let client = pool.get().await.unwrap();
     let pwd = env::current_dir().unwrap();
@@ -81,15 +167,65 @@ async fn load_art57(pool: &Pool) {
     debug!("{}", path);
     let query = format!(
         "DROP TABLE IF EXISTS ctgov.article57_rxnorm;
-        CREATE TABLE ctgov.article57_rxnorm (name TEXT,	ingredient TEXT,	rxcui TEXT);
-        COPY ctgov.article57_rxnorm FROM {} WITH DELIMITER E'\\t' CSV HEADER QUOTE E'\\b'",
+        CREATE TABLE ctgov.article57_rxnorm (name TEXT,	ingredient TEXT,	rxcui TEXT,	clean TEXT);
+        COPY ctgov.article57_rxnorm (name, ingredient, rxcui) FROM {} WITH DELIMITER E'\\t' CSV HEADER QUOTE E'\\b'",
         path
     );
 
     client.batch_execute(query.as_str()).await.unwrap();
+    normalize_column::<String>(
+        "find_art57_names",
+        "update_art57_names",
+        &client,
+        queries,
+        normalizer,
+    )
+    .await;
 }
 
-pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
+/// Copies the RxNorm strings into ctgov.rxnorm_terms, normalized the same way as the
+/// interventions and descriptions they are compared with.
+async fn load_rxnorm_terms(
+    pool: &Pool,
+    queries: &HashMap<String, String>,
+    normalizer: &Normalizer,
+) {
+    info!("Normalizing RxNorm strings");
+    let client = pool.get().await.unwrap();
+    execute("drop_rxnorm_terms", &client, queries).await;
+    execute("create_rxnorm_terms", &client, queries).await;
+
+    let rows = query("find_rxnorm_strings", &client, queries, &[]).await;
+    let mut terms: HashSet<(i32, String, String, String)> = HashSet::new();
+    for row in rows {
+        let str: String = row.get("str");
+        terms.insert((
+            row.get("rxcui"),
+            row.get("sab"),
+            row.get("tty"),
+            normalizer.normalize(&str),
+        ));
+    }
+
+    let terms: Vec<(i32, String, String, String)> = terms.into_iter().collect();
+    for chunk in terms.chunks(100000) {
+        let rxcuis: Vec<i32> = chunk.iter().map(|t| t.0).collect();
+        let sabs: Vec<&str> = chunk.iter().map(|t| t.1.as_str()).collect();
+        let ttys: Vec<&str> = chunk.iter().map(|t| t.2.as_str()).collect();
+        let strs: Vec<&str> = chunk.iter().map(|t| t.3.as_str()).collect();
+        execute_params(
+            "insert_rxnorm_terms",
+            &client,
+            queries,
+            &[&rxcuis, &sabs, &ttys, &strs],
+        )
+        .await;
+    }
+    execute("rxnorm_terms_index", &client, queries).await;
+    info!("Stored {} normalized RxNorm strings", terms.len());
+}
+
+pub async fn read_descriptions(pool: &Pool, normalizer: &Normalizer) -> Result<(), Box<dyn Error>> {
     let queries = Loader::read_queries_from("./sql/drug_mapping.sql").unwrap();
 
     let client = pool.get().await?;
@@ -97,7 +233,14 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
     execute("rg_desc_map", &client, &queries).await;
     execute("drop_join_table_rg", &client, &queries).await;
     execute("create_join_table_rg", &client, &queries).await;
-    execute("remove_non_alpha_numeric_rg", &client, &queries).await;
+    normalize_column::<i64>(
+        "find_descriptions_to_clean",
+        "update_descriptions",
+        &client,
+        &queries,
+        normalizer,
+    )
+    .await;
     execute("pre_and_append_spaces_rg", &client, &queries).await;
 
     let ttys = get_ttys();
@@ -121,7 +264,7 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
 
         do_matching(rxnorm, &mapping, &mut insert_values, &mut map);
 
-        if map.len() == 0 {
+        if map.is_empty() {
             warn!("Did not find any matches, something is probably wrong");
             continue;
         }
@@ -140,7 +283,7 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
                 "UPDATE ctgov.rg_desc_mapping SET description = replace(description, '{}', '') WHERE id IN ({});",
                 ing, ids
             );
-            update_queries.push_str(&*query);
+            update_queries.push_str(&query);
         }
         client.batch_execute(update_queries.as_str()).await.unwrap();
     }
@@ -153,9 +296,15 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
     execute("i_over_dg_rg_rx_table", &client, &queries).await;
     execute("io_over_dg_rg_rx_table", &client, &queries).await;
 
+    info!("Resolving the attached rxnorm concepts to their ingredients");
+    execute("drop_ingredient_paths", &client, &queries).await;
+    execute("create_ingredient_paths", &client, &queries).await;
+
     info!("Creating the final result group to rxcui table");
     execute("drop_final_rg_in_table", &client, &queries).await;
     execute("create_final_rg_in_table", &client, &queries).await;
+    execute("drop_final_rg_combination_table", &client, &queries).await;
+    execute("create_final_rg_combination_table", &client, &queries).await;
     // execute("get_dose_1", &client, &queries).await;
 
     Ok(())
@@ -163,23 +312,34 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
 
 fn get_ttys() -> Vec<(&'static str, &'static str)> {
     vec![
+        ("MIN", "Multiple Ingredients"),
         ("PIN", "Precise Ingredients"),
         ("IN", "Ingredients"),
         ("BN", "Brand Names"),
     ]
 }
 
+/// Matches the names as reported against the RxNorm and article 57 strings, before normalization
+/// can merge them with other names
+async fn raw_match(queries: &HashMap<String, String>, pool: &Pool) {
+    info!("Looking for matches before normalization...");
+    let client = pool.get().await.unwrap();
+    execute("raw_match_rxnconso", &client, queries).await;
+    execute("raw_match_art57", &client, queries).await;
+    execute("remove_matches", &client, queries).await;
+}
+
 async fn direct_match(queries: &HashMap<String, String>, pool: &Pool) {
     info!("Looking for matches...");
     let client = pool.get().await.unwrap();
-    execute("direct_match_rxnconso", &client, &queries).await;
-    execute("direct_match_art57", &client, &queries).await;
+    execute("direct_match_rxnconso", &client, queries).await;
+    execute("direct_match_art57", &client, queries).await;
     // TODO: Check how much this would add in value
-    //execute("synonyms", &client, &queries).await;
-    execute("remove_matches", &client, &queries).await;
+    //execute("synonyms", &client, queries).await;
+    execute("remove_matches", &client, queries).await;
 }
 
-async fn match_words(pool: &Pool, tty: &&str, queries: &HashMap<String, String>) {
+async fn match_words(pool: &Pool, tty: &str, queries: &HashMap<String, String>) {
     let client = pool.get().await.unwrap();
 
     let q = queries.get("find_rxconso_terms_for_tty").unwrap();
@@ -194,7 +354,7 @@ async fn match_words(pool: &Pool, tty: &&str, queries: &HashMap<String, String>)
     let mut map: HashMap<String, String> = HashMap::new();
 
     do_matching(rxnorm, &mapping, &mut insert_values, &mut map);
-    if map.len() == 0 {
+    if map.is_empty() {
         warn!("Did not find any matches, something is probably wrong");
         return;
     }
@@ -207,14 +367,15 @@ async fn match_words(pool: &Pool, tty: &&str, queries: &HashMap<String, String>)
             "UPDATE ctgov.drug_mapping SET clean = replace(clean, '{}', '') WHERE id IN ({});",
             ing, ids
         );
-        update_queries.push_str(&*query);
+        update_queries.push_str(&query);
     }
 
     // removing trailing comma
     insert_values.pop();
     let q = format!(
-        "INSERT INTO ctgov.drug_mapping_rxcui(drug_mapping_id,rxcui) VALUES {};",
-        insert_values
+        "INSERT INTO ctgov.drug_mapping_rxcui(drug_mapping_id,rxcui,match_method)
+        SELECT id, rxcui, 'word match {}' FROM (VALUES {}) AS v(id, rxcui);",
+        tty, insert_values
     );
     let results = tokio::join!(
         client.batch_execute(update_queries.as_str()),
@@ -228,43 +389,167 @@ async fn match_words(pool: &Pool, tty: &&str, queries: &HashMap<String, String>)
     info!("Executed the update statements in the drug_mapping table");
 }
 
+/// Replaces regimen acronyms such as FOLFOX by the ingredients they consist of, the acronym is
+/// removed from the clean name so the remaining text can still be matched to RxNorm.
+async fn expand_regimens(pool: &Pool, regimens: &[Regimen], queries: &HashMap<String, String>) {
+    info!(
+        "Going to check if titles or names contain any of {} regimens",
+        regimens.len()
+    );
+    let client = pool.get().await.unwrap();
+
+    let ingredients: Vec<&String> = regimens.iter().flat_map(|r| &r.ingredients).collect();
+    let rows = query("find_ingredient_rxcuis", &client, queries, &[&ingredients]).await;
+    let mut rxcuis: HashMap<String, Vec<i32>> = HashMap::new();
+    for row in rows {
+        rxcuis
+            .entry(row.get("str"))
+            .or_default()
+            .push(row.get("rxcui"));
+    }
+    for regimen in regimens {
+        for ingredient in &regimen.ingredients {
+            if !rxcuis.contains_key(ingredient) {
+                warn!(
+                    "No RxNorm ingredient found for {} in {}",
+                    ingredient, regimen.name
+                );
+            }
+        }
+    }
+
+    let terms = query("find_terms_to_clean", &client, queries, &[]).await;
+    let mut ids: Vec<i64> = Vec::new();
+    let mut cleaned: Vec<String> = Vec::new();
+    let mut matches: (Vec<i64>, Vec<i32>, Vec<String>) = (vec![], vec![], vec![]);
+    for row in terms {
+        let id: i64 = row.get("id");
+        let mut clean: String = row.get("term");
+        let mut expanded = false;
+        for regimen in regimens {
+            let acronym = format!(" {} ", regimen.acronym);
+            if !clean.contains(&acronym) {
+                continue;
+            }
+            expanded = true;
+            clean = clean.replace(&acronym, " ");
+            for rxcui in regimen
+                .ingredients
+                .iter()
+                .flat_map(|i| rxcuis.get(i))
+                .flatten()
+            {
+                matches.0.push(id);
+                matches.1.push(*rxcui);
+                matches.2.push(regimen.detail());
+            }
+        }
+        if expanded {
+            ids.push(id);
+            cleaned.push(clean);
+        }
+    }
+    info!("Expanded regimens in {} titles or names", ids.len());
+
+    execute_params(
+        "insert_matches",
+        &client,
+        queries,
+        &[&matches.0, &matches.1, &matches.2, &"regimen"],
+    )
+    .await;
+    execute_params("update_clean", &client, queries, &[&ids, &cleaned]).await;
+}
+
+/// Matches the words that are left in the clean names to the RxNorm ingredient or brand name
+/// closest to it, e.g. 'metformine' to 'metformin'. Words with several equally close names are
+/// skipped.
+async fn fuzzy_match(pool: &Pool, fuzzy: &FuzzyMatching, queries: &HashMap<String, String>) {
+    info!(
+        "Fuzzy matching the remaining words to RxNorm ingredients and brand names within a distance of {}",
+        fuzzy.max_distance
+    );
+    let client = pool.get().await.unwrap();
+    let min_length = fuzzy.min_length as i32;
+    let rows = query("find_fuzzy_candidates", &client, queries, &[&min_length]).await;
+    let mut rxcuis: HashMap<String, Vec<i32>> = HashMap::new();
+    let mut tree = BkTree::default();
+    for row in rows {
+        let str: String = row.get("str");
+        tree.insert(&str);
+        rxcuis.entry(str).or_default().push(row.get("rxcui"));
+    }
+
+    let terms = query("find_terms_to_clean", &client, queries, &[]).await;
+    let mut matches: (Vec<i64>, Vec<i32>, Vec<String>) = (vec![], vec![], vec![]);
+    for row in terms {
+        let id: i64 = row.get("id");
+        let clean: String = row.get("term");
+        for word in clean.split_whitespace() {
+            if word.chars().count() < fuzzy.min_length {
+                continue;
+            }
+            let found = tree.find(word, fuzzy.max_distance);
+            let best = match found.iter().map(|f| f.1).min() {
+                Some(best) => best,
+                None => continue,
+            };
+            let closest: Vec<&str> = found.iter().filter(|f| f.1 == best).map(|f| f.0).collect();
+            if closest.len() > 1 {
+                debug!("{} is equally close to {:?}, skipping", word, closest);
+                continue;
+            }
+            for rxcui in &rxcuis[closest[0]] {
+                matches.0.push(id);
+                matches.1.push(*rxcui);
+                matches
+                    .2
+                    .push(format!("{} ~ {} ({})", word, closest[0], best));
+            }
+        }
+    }
+    info!("Found {} fuzzy matches", matches.0.len());
+
+    execute_params(
+        "insert_matches",
+        &client,
+        queries,
+        &[&matches.0, &matches.1, &matches.2, &"fuzzy"],
+    )
+    .await;
+    execute("remove_matches", &client, queries).await;
+}
+
 fn do_matching(
     rxnorm: Vec<Row>,
-    mapping: &Vec<Row>,
+    mapping: &[Row],
     insert_values: &mut String,
     map: &mut HashMap<String, String>,
 ) {
-    let total: i64 = (rxnorm.len() * mapping.len()) as i64;
+    let total = rxnorm.len() * mapping.len();
     info!("Making {} string comparisons ...", total);
-    let mut pb = ProgressBar::new((total / 1000000) as u64);
-    pb.set_width(Some(80));
-    let mut counter: i64 = 0;
+    let progress = Progress::new(total);
     for r in rxnorm {
         let drug: String = r.get("str");
         let drug_with_spaces = format!(" {} ", drug);
+        progress.inc_by(mapping.len());
         for m in mapping {
-            counter += 1;
-            if counter % 1000000 == 0 {
-                pb.inc();
-            }
             let source: String = m.get("clean");
             if source.contains(&drug_with_spaces) {
                 let id: i64 = m.get("id");
                 let cui: i32 = r.get("rxcui");
 
                 let value = format!("({},{}),", id, cui);
-                insert_values.push_str(&*value);
+                insert_values.push_str(&value);
 
-                if map.contains_key(&drug) {
-                    let mut addition: String = String::from(",");
-                    addition.push_str(&*id.to_string());
-                    map.get_mut(&drug).unwrap().push_str(&*addition);
+                if let Some(ids) = map.get_mut(&drug) {
+                    ids.push(',');
+                    ids.push_str(&id.to_string());
                 } else {
                     map.insert(drug.to_owned(), id.to_string());
                 }
             }
         }
     }
-    pb.finish();
-    println!();
+    progress.finish();
 }
@@ -9,8 +9,15 @@ use log::info;
 use rawsql::Loader;
 
 use crate::db::execute;
-
-pub async fn find_drugs(pool: &Pool) -> Result<(), Box<dyn Error>> {
+use crate::drug_lists::DrugLists;
+use crate::drug_mapping::{clean_terms, remove_junk, sham_to_placebo};
+use crate::normalize::{normalize_column, Normalizer};
+
+pub async fn find_drugs(
+    pool: &Pool,
+    normalizer: &Normalizer,
+    lists: &DrugLists,
+) -> Result<(), Box<dyn Error>> {
     let queries = Loader::read_queries_from("./sql/drug_mapping.sql").unwrap();
 
     let client = pool.get().await?;
@@ -21,33 +28,25 @@ pub async fn find_drugs(pool: &Pool) -> Result<(), Box<dyn Error>> {
     execute("create_mapping_table", &client, &queries).await;
     execute("mapping_clean_index", &client, &queries).await;
     execute("create_join_table", &client, &queries).await;
-    execute("remove_junk", &client, &queries).await;
+    remove_junk(&client, &queries, lists).await;
 
-    direct_match(&queries, &pool).await;
+    direct_match(&queries, pool).await;
 
-    info!("Regex cleaning of data");
-    execute("remove_q2w", &client, &queries).await;
-    execute("remove_non_alpha_numeric", &client, &queries).await;
-    execute("sham_to_placebo", &client, &queries).await;
-    execute("remove_qd", &client, &queries).await;
-    execute("remove_group", &client, &queries).await;
-    execute("remove_cohort", &client, &queries).await;
-    execute("remove_arm", &client, &queries).await;
-    execute("remove_spaces", &client, &queries).await;
-    execute("trim", &client, &queries).await;
-    execute("remove_junk", &client, &queries).await;
+    info!("Normalizing interventions and group titles");
+    clean_terms(&client, &queries, normalizer).await;
+    sham_to_placebo(&client, &queries, lists).await;
+    remove_junk(&client, &queries, lists).await;
 
-    direct_match(&queries, &pool).await;
+    direct_match(&queries, pool).await;
 
     execute("pre_and_append_spaces", &client, &queries).await;
 
-    match_words(&pool, &queries).await;
+    match_words(pool, &queries, normalizer).await;
 
-    execute("remove_spaces", &client, &queries).await;
-    execute("trim", &client, &queries).await;
-    execute("remove_junk", &client, &queries).await;
+    clean_terms(&client, &queries, normalizer).await;
+    remove_junk(&client, &queries, lists).await;
 
-    direct_match(&queries, &pool).await;
+    direct_match(&queries, pool).await;
 
     info!("Creating join tables for mapped rxcuis");
     execute("drop_join_table_1", &client, &queries).await;
@@ -63,7 +62,7 @@ pub async fn find_drugs(pool: &Pool) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
+pub async fn read_descriptions(pool: &Pool, normalizer: &Normalizer) -> Result<(), Box<dyn Error>> {
     let queries = Loader::read_queries_from("./sql/drug_mapping.sql").unwrap();
 
     let client = pool.get().await?;
@@ -71,7 +70,14 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
     execute("rg_desc_map", &client, &queries).await;
     execute("drop_join_table_rg", &client, &queries).await;
     execute("create_join_table_rg", &client, &queries).await;
-    execute("remove_non_alpha_numeric_rg", &client, &queries).await;
+    normalize_column::<i64>(
+        "find_descriptions_to_clean",
+        "update_descriptions",
+        &client,
+        &queries,
+        normalizer,
+    )
+    .await;
     execute("pre_and_append_spaces_rg", &client, &queries).await;
 
     let chembl_drugs: Vec<Row> = all_chembl(&client, &queries).await;
@@ -93,9 +99,9 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
         let mut insert_values: String = String::from("");
         let mut map: HashMap<String, String> = HashMap::new();
 
-        do_matching(drug, &mapping, &mut insert_values, &mut map);
+        do_matching(drug, &mapping, &mut insert_values, &mut map, normalizer);
 
-        if map.len() == 0 {
+        if map.is_empty() {
             continue;
         }
         info!("Found matches, updating db");
@@ -114,7 +120,7 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
                 "UPDATE ctgov.rg_desc_mapping SET description = replace(description, '{}', '') WHERE id IN ({});",
                 ing, ids
             );
-            update_queries.push_str(&*query);
+            update_queries.push_str(&query);
         }
         client.batch_execute(update_queries.as_str()).await.unwrap();
     }
@@ -127,6 +133,9 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
     execute("i_over_dg_rg_rx_table", &client, &queries).await;
     execute("io_over_dg_rg_rx_table", &client, &queries).await;
 
+    execute("drop_ingredient_paths", &client, &queries).await;
+    execute("create_ingredient_paths", &client, &queries).await;
+
     info!("Creating the final result group to rxcui table");
     execute("drop_final_rg_in_table", &client, &queries).await;
     execute("create_final_rg_in_table", &client, &queries).await;
@@ -138,13 +147,13 @@ pub async fn read_descriptions(pool: &Pool) -> Result<(), Box<dyn Error>> {
 async fn direct_match(queries: &HashMap<String, String>, pool: &Pool) {
     info!("Looking for direct matches...");
     let client = pool.get().await.unwrap();
-    execute("direct_match_chembl", &client, &queries).await;
-    execute("remove_matches", &client, &queries).await;
+    execute("direct_match_chembl", &client, queries).await;
+    execute("remove_matches", &client, queries).await;
 }
 
-async fn match_words(pool: &Pool, queries: &HashMap<String, String>) {
+async fn match_words(pool: &Pool, queries: &HashMap<String, String>, normalizer: &Normalizer) {
     let client = pool.get().await.unwrap();
-    let chembl_drugs = all_chembl(&client, &queries).await;
+    let chembl_drugs = all_chembl(&client, queries).await;
     let mut reload = true;
     let mut q = queries.get("find_terms_to_map").unwrap();
     let mut mapping = client.query(q.as_str(), &[]).await.unwrap();
@@ -161,8 +170,8 @@ async fn match_words(pool: &Pool, queries: &HashMap<String, String>) {
         // A map of terms and ids to use in the update statement
         let mut map: HashMap<String, String> = HashMap::new();
 
-        do_matching(drug, &mapping, &mut insert_values, &mut map);
-        if map.len() == 0 {
+        do_matching(drug, &mapping, &mut insert_values, &mut map, normalizer);
+        if map.is_empty() {
             continue;
         }
         info!("Found matches, updating db");
@@ -175,13 +184,14 @@ async fn match_words(pool: &Pool, queries: &HashMap<String, String>) {
                 "UPDATE ctgov.drug_mapping SET clean = replace(clean, '{}', '') WHERE id IN ({});",
                 ing, ids
             );
-            update_queries.push_str(&*query);
+            update_queries.push_str(&query);
         }
 
         // removing trailing comma
         insert_values.pop();
         let q = format!(
-            "INSERT INTO ctgov.drug_mapping_rxcui(drug_mapping_id,rxcui) VALUES {};",
+            "INSERT INTO ctgov.drug_mapping_rxcui(drug_mapping_id,rxcui,match_method)
+            SELECT id, rxcui, 'word match chembl' FROM (VALUES {}) AS v(id, rxcui);",
             insert_values
         );
         let results = tokio::join!(
@@ -203,11 +213,13 @@ async fn all_chembl(client: &Object<Manager>, queries: &HashMap<String, String>)
 
 fn do_matching(
     c: Row,
-    mapping: &Vec<Row>,
+    mapping: &[Row],
     insert_values: &mut String,
     map: &mut HashMap<String, String>,
+    normalizer: &Normalizer,
 ) {
-    let drug: String = c.get("synonyms");
+    let synonym: String = c.get("synonyms");
+    let drug = normalizer.normalize(&synonym);
     let drug_with_spaces = format!(" {} ", drug);
     info!("{}", drug_with_spaces);
     for m in mapping {
@@ -217,12 +229,11 @@ fn do_matching(
             let cui: i64 = c.get("molregno");
 
             let value = format!("({},{}),", id, cui);
-            insert_values.push_str(&*value);
+            insert_values.push_str(&value);
 
-            if map.contains_key(&drug) {
-                let mut addition: String = String::from(",");
-                addition.push_str(&*id.to_string());
-                map.get_mut(&drug).unwrap().push_str(&*addition);
+            if let Some(ids) = map.get_mut(&drug) {
+                ids.push(',');
+                ids.push_str(&id.to_string());
             } else {
                 map.insert(drug.to_owned(), id.to_string());
             }
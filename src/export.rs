@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    BooleanBuilder, Float32Builder, Float64Builder, Int16Builder, Int32Builder, Int64Builder,
+    StringBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use chrono::Local;
+use config::Config;
+use deadpool::managed::Object;
+use deadpool_postgres::tokio_postgres::Row;
+use deadpool_postgres::{Manager, Pool};
+use futures::{pin_mut, TryStreamExt};
+use log::info;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use rawsql::Loader;
+use serde_json::{json, Value};
+
+use crate::db::query;
+use crate::run_log::RunLog;
+
+const BATCH_SIZE: usize = 65_536;
+
+/// Where the export is written and which ctgov tables are exported
+pub struct Export {
+    dir: String,
+    tables: Vec<String>,
+}
+
+impl Export {
+    pub fn from_settings(settings: &Config) -> Self {
+        let dir = settings
+            .get_string("export_dir")
+            .unwrap_or_else(|_| String::from("export"));
+        let tables: Vec<String> = match settings.get_array("export_tables") {
+            Ok(tables) => tables
+                .into_iter()
+                .map(|t| t.into_string().expect("export_tables must be table names"))
+                .collect(),
+            Err(_) => vec![
+                String::from("result_group_ingredient"),
+                String::from("matches"),
+                String::from("reported_events_meddra"),
+            ],
+        };
+        for table in &tables {
+            if !table
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                panic!("Invalid table name in export_tables: {:?}", table);
+            }
+        }
+        Self { dir, tables }
+    }
+
+    /// Writes every table as CSV and Parquet, streaming the rows, together with a metadata.json
+    /// holding the columns and row count of every table and the run log
+    pub async fn write(&self, pool: &Pool, run_log: &RunLog) -> Result<(), Box<dyn Error>> {
+        info!("Exporting {} tables to {}", self.tables.len(), self.dir);
+        fs::create_dir_all(&self.dir)?;
+        let client = pool.get().await?;
+        let queries = Loader::read_queries_from("./sql/export.sql").unwrap();
+
+        let mut tables: Vec<Value> = Vec::new();
+        for table in &self.tables {
+            let columns: Vec<(String, String)> = query("find_columns", &client, &queries, &[table])
+                .await
+                .iter()
+                .map(|r| (r.get("column_name"), r.get("data_type")))
+                .collect();
+            if columns.is_empty() {
+                panic!("Table ctgov.{} does not exist, run Dracula first", table);
+            }
+            let csv = Path::new(&self.dir).join(format!("{}.csv", table));
+            write_csv(&client, table, &csv).await?;
+            let parquet = Path::new(&self.dir).join(format!("{}.parquet", table));
+            let rows = write_parquet(&client, table, &columns, &parquet).await?;
+            info!("Exported {} rows of ctgov.{}", rows, table);
+            tables.push(json!({
+                "table": format!("ctgov.{}", table),
+                "rows": rows,
+                "files": [format!("{}.csv", table), format!("{}.parquet", table)],
+                "columns": columns
+                    .iter()
+                    .map(|(name, data_type)| json!({"name": name, "type": data_type}))
+                    .collect::<Vec<Value>>(),
+            }));
+        }
+
+        let mut runs: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        for row in query("find_run_log", &client, &queries, &[]).await {
+            runs.entry(row.get("run_id"))
+                .or_default()
+                .insert(row.get("key"), row.get("value"));
+        }
+        let metadata = json!({
+            "exported_at": Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            "tables": tables,
+            "runs": runs,
+        });
+        let path = Path::new(&self.dir).join("metadata.json");
+        fs::write(&path, serde_json::to_string_pretty(&metadata)?)?;
+        run_log.record(pool, "export", &self.dir).await;
+        Ok(())
+    }
+}
+
+async fn write_csv(
+    client: &Object<Manager>,
+    table: &str,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    info!("Writing {}", path.display());
+    let copy = format!(
+        "COPY (SELECT * FROM ctgov.{}) TO STDOUT WITH (FORMAT csv, HEADER)",
+        table
+    );
+    let stream = client.copy_out(copy.as_str()).await?;
+    pin_mut!(stream);
+    let mut file = BufWriter::new(File::create(path)?);
+    while let Some(chunk) = stream.try_next().await? {
+        file.write_all(&chunk)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+async fn write_parquet(
+    client: &Object<Manager>,
+    table: &str,
+    columns: &[(String, String)],
+    path: &Path,
+) -> Result<usize, Box<dyn Error>> {
+    info!("Writing {}", path.display());
+    let mut builders: Vec<Column> = columns.iter().map(|c| Column::new(&c.1)).collect();
+    let schema = Arc::new(Schema::new(
+        columns
+            .iter()
+            .zip(&builders)
+            .map(|(c, b)| Field::new(&c.0, b.data_type(), true))
+            .collect::<Vec<Field>>(),
+    ));
+    // Types without a Parquet counterpart here, such as dates, are exported as text
+    let select: Vec<String> = columns
+        .iter()
+        .zip(&builders)
+        .map(|(c, b)| match b {
+            Column::Text(_) => format!("cast(\"{}\" AS TEXT)", c.0),
+            _ => format!("\"{}\"", c.0),
+        })
+        .collect();
+    let sql = format!("SELECT {} FROM ctgov.{}", select.join(", "), table);
+
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(props))?;
+    let params: Vec<String> = Vec::new();
+    let stream = client.query_raw(sql.as_str(), &params).await?;
+    pin_mut!(stream);
+    let mut rows = 0;
+    let mut batched = 0;
+    while let Some(row) = stream.try_next().await? {
+        for (i, builder) in builders.iter_mut().enumerate() {
+            builder.append(&row, i);
+        }
+        rows += 1;
+        batched += 1;
+        if batched == BATCH_SIZE {
+            writer.write(&finish_batch(&schema, &mut builders)?)?;
+            batched = 0;
+        }
+    }
+    if batched > 0 {
+        writer.write(&finish_batch(&schema, &mut builders)?)?;
+    }
+    writer.close()?;
+    Ok(rows)
+}
+
+fn finish_batch(
+    schema: &Arc<Schema>,
+    builders: &mut [Column],
+) -> Result<RecordBatch, Box<dyn Error>> {
+    let arrays: Vec<ArrayRef> = builders.iter_mut().map(|b| b.finish()).collect();
+    Ok(RecordBatch::try_new(schema.clone(), arrays)?)
+}
+
+/// Collects the values of a column of a batch, by the Postgres data type of the column
+enum Column {
+    Int16(Int16Builder),
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    Float32(Float32Builder),
+    Float64(Float64Builder),
+    Boolean(BooleanBuilder),
+    Text(StringBuilder),
+}
+
+impl Column {
+    fn new(data_type: &str) -> Self {
+        match data_type {
+            "smallint" => Column::Int16(Int16Builder::new()),
+            "integer" => Column::Int32(Int32Builder::new()),
+            "bigint" => Column::Int64(Int64Builder::new()),
+            "real" => Column::Float32(Float32Builder::new()),
+            "double precision" => Column::Float64(Float64Builder::new()),
+            "boolean" => Column::Boolean(BooleanBuilder::new()),
+            _ => Column::Text(StringBuilder::new()),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Column::Int16(_) => DataType::Int16,
+            Column::Int32(_) => DataType::Int32,
+            Column::Int64(_) => DataType::Int64,
+            Column::Float32(_) => DataType::Float32,
+            Column::Float64(_) => DataType::Float64,
+            Column::Boolean(_) => DataType::Boolean,
+            Column::Text(_) => DataType::Utf8,
+        }
+    }
+
+    fn append(&mut self, row: &Row, i: usize) {
+        match self {
+            Column::Int16(b) => b.append_option(row.get::<_, Option<i16>>(i)),
+            Column::Int32(b) => b.append_option(row.get::<_, Option<i32>>(i)),
+            Column::Int64(b) => b.append_option(row.get::<_, Option<i64>>(i)),
+            Column::Float32(b) => b.append_option(row.get::<_, Option<f32>>(i)),
+            Column::Float64(b) => b.append_option(row.get::<_, Option<f64>>(i)),
+            Column::Boolean(b) => b.append_option(row.get::<_, Option<bool>>(i)),
+            Column::Text(b) => b.append_option(row.get::<_, Option<&str>>(i)),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Column::Int16(b) => Arc::new(b.finish()),
+            Column::Int32(b) => Arc::new(b.finish()),
+            Column::Int64(b) => Arc::new(b.finish()),
+            Column::Float32(b) => Arc::new(b.finish()),
+            Column::Float64(b) => Arc::new(b.finish()),
+            Column::Boolean(b) => Arc::new(b.finish()),
+            Column::Text(b) => Arc::new(b.finish()),
+        }
+    }
+}
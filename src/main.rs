@@ -1,20 +1,47 @@
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
 use std::io::Write;
 use std::time::Instant;
 
 use chrono::Local;
 use config::{Config, File};
+use deadpool_postgres::Pool;
 use env_logger::Builder;
 use log::{error, info, LevelFilter};
 
 use crate::arm_to_intervention::connect_arms_to_interventions;
-use crate::meddra_mapping::find_pts;
+use crate::ctcae::read_correspondence;
+use crate::drug_lists::DrugLists;
+use crate::drug_mapping::FuzzyMatching;
+use crate::export::Export;
+use crate::meddra_mapping::{find_pts, Routing, Scoring};
+use crate::meddra_review::Curation;
+use crate::meddra_tokens::TokenMatcher;
+use crate::normalize::{Normalizer, DRUG_RULES, MEDDRA_RULES};
+use crate::regimens::read_regimens;
+use crate::run_log::RunLog;
 
 mod arm_to_intervention;
+mod bk_tree;
+mod contingency;
+mod ctcae;
 mod db;
+mod drug_lists;
 mod drug_mapping;
 mod drug_mapping_chembl;
+mod export;
 mod meddra_mapping;
+mod meddra_migration;
+mod meddra_review;
+mod meddra_tokens;
+mod normalize;
+mod omop_export;
+mod progress;
+mod regimens;
+mod run_log;
+mod signals;
+mod snomed_mapping;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -31,18 +58,84 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     info!("Initializing DB pool");
     let pool = db::init_db_pool(&settings);
+    let run_log = RunLog::start(&pool).await;
 
+    match env::args().nth(1).as_deref() {
+        None => {}
+        Some("migrate-meddra") => {
+            let version = settings
+                .get_string("meddra_version")
+                .expect("Could not read meddra_version from the settings file");
+            meddra_migration::migrate(&pool, &run_log, &version).await?;
+            count_events(&pool, &settings).await?;
+            if settings.get_bool("snomed").unwrap_or(false) {
+                snomed_mapping::map_to_snomed(&pool, &run_log).await?;
+            }
+            print_end(start);
+            return Ok(());
+        }
+        Some("review") => {
+            let scoring = Scoring::from_settings(&settings);
+            let curation = Curation::from_settings(&settings);
+            let margin = settings.get_int("meddra_review_margin").unwrap_or(3) as usize;
+            meddra_review::review(&pool, &curation, scoring.max_score, margin).await?;
+            print_end(start);
+            return Ok(());
+        }
+        Some("export-omop") => {
+            omop_export::export(&pool, &run_log).await?;
+            print_end(start);
+            return Ok(());
+        }
+        Some("export") => {
+            Export::from_settings(&settings)
+                .write(&pool, &run_log)
+                .await?;
+            print_end(start);
+            return Ok(());
+        }
+        Some(command) => panic!(
+            "Unknown command {:?}, run without arguments or with migrate-meddra, review, export-omop or export",
+            command
+        ),
+    }
+
+    let drug_normalizer = Normalizer::from_settings(&settings, "drug_normalization", &DRUG_RULES);
+    let drug_lists = DrugLists::from_settings(&settings).normalize(&drug_normalizer);
+    run_log
+        .record(&pool, "drug_lists_version", &drug_lists.version())
+        .await;
+    drug_lists.store(&pool).await;
     let drug_ref = settings
         .get_string("drug_ref")
         .expect("Could not read drug_ref from the settings file");
     if drug_ref.eq("chembl") {
-        drug_mapping_chembl::find_drugs(&pool).await?;
-        connect_arms_to_interventions(&pool).await?;
-        drug_mapping_chembl::read_descriptions(&pool).await?;
+        drug_mapping_chembl::find_drugs(&pool, &drug_normalizer, &drug_lists).await?;
+        connect_arms_to_interventions(&pool, &drug_lists).await?;
+        drug_mapping_chembl::read_descriptions(&pool, &drug_normalizer).await?;
     } else if drug_ref.eq("rxnorm") {
-        drug_mapping::find_drugs(&pool).await?;
-        connect_arms_to_interventions(&pool).await?;
-        drug_mapping::read_descriptions(&pool).await?;
+        let regimens = match settings.get_string("regimens") {
+            Ok(path) => {
+                run_log.record(&pool, "regimens", &path).await;
+                read_regimens(&path, &drug_normalizer)
+            }
+            Err(_) => vec![],
+        };
+        let fuzzy = FuzzyMatching::from_settings(&settings);
+        let exclude_ambiguous_brands = settings
+            .get_bool("exclude_ambiguous_brands")
+            .unwrap_or(false);
+        drug_mapping::find_drugs(
+            &pool,
+            &drug_normalizer,
+            &drug_lists,
+            &regimens,
+            &fuzzy,
+            exclude_ambiguous_brands,
+        )
+        .await?;
+        connect_arms_to_interventions(&pool, &drug_lists).await?;
+        drug_mapping::read_descriptions(&pool, &drug_normalizer).await?;
     } else {
         error!("Unkown value passed as drug_ref");
         panic!(
@@ -55,10 +148,45 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .get_bool("skip_meddra")
         .expect("Could not read skip_meddra from the settings file");
     if !skip_meddra {
-        let precision = settings
-            .get_int("meddra_precision")
-            .expect("Could not read meddra_precision from settings file");
-        find_pts(&pool, precision).await?;
+        let scoring = Scoring::from_settings(&settings);
+        let meddra_version = settings
+            .get_string("meddra_version")
+            .unwrap_or_else(|_| String::from("unknown"));
+        run_log
+            .record(&pool, "meddra_version", &meddra_version)
+            .await;
+        let meddra_normalizer =
+            Normalizer::from_settings(&settings, "meddra_normalization", &MEDDRA_RULES);
+        let tokens = TokenMatcher::from_settings(&settings, &meddra_normalizer);
+        let ctcae = match settings.get_string("ctcae_correspondence") {
+            Ok(path) => {
+                run_log.record(&pool, "ctcae_correspondence", &path).await;
+                read_correspondence(&path, &meddra_normalizer)
+            }
+            Err(_) => HashMap::new(),
+        };
+        let curation = Curation::from_settings(&settings);
+        run_log
+            .record(
+                &pool,
+                "meddra_curation",
+                &format!("{} ({} terms)", curation.path, curation.len()),
+            )
+            .await;
+        find_pts(
+            &pool,
+            &scoring,
+            &meddra_normalizer,
+            &tokens,
+            &ctcae,
+            &curation,
+            &Routing::from_settings(&settings),
+        )
+        .await?;
+        count_events(&pool, &settings).await?;
+        if settings.get_bool("snomed").unwrap_or(false) {
+            snomed_mapping::map_to_snomed(&pool, &run_log).await?;
+        }
     } else {
         info!("Skipping MedDRA standardization step")
     }
@@ -67,6 +195,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Counts the events per ingredient and pools them into signals, the counts read the ingredients
+/// and matches of the rxnorm drug mapping so they are skipped with the chembl one
+async fn count_events(pool: &Pool, settings: &Config) -> Result<(), Box<dyn Error>> {
+    let drug_ref = settings
+        .get_string("drug_ref")
+        .expect("Could not read drug_ref from the settings file");
+    if drug_ref.eq("rxnorm") {
+        contingency::count_events(pool).await?;
+        signals::compute(pool).await?;
+    } else {
+        info!("Skipping event counts and signals, they need drug_ref = \"rxnorm\"")
+    }
+    Ok(())
+}
+
 fn init_logger() {
     let mut builder = Builder::from_default_env();
 
@@ -1,16 +1,138 @@
-use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
+use std::thread;
 
+use config::Config;
+use deadpool::managed::Object;
 use deadpool_postgres::tokio_postgres::Row;
-use deadpool_postgres::Pool;
-use log::{debug, info};
-use pbr::ProgressBar;
+use deadpool_postgres::{Manager, Pool};
+use log::{debug, info, warn};
 use rawsql::Loader;
+use tokio::task;
 
-use crate::db::{execute, query};
+use crate::ctcae::parse_grade;
+use crate::db::{execute, execute_params, query};
+use crate::meddra_review::Curation;
+use crate::meddra_tokens::TokenMatcher;
+use crate::normalize::Normalizer;
+use crate::progress::Progress;
 
-pub async fn find_pts(pool: &Pool, precision: i64) -> Result<(), Box<dyn Error>> {
+/// An adverse event term as reported (lowercased) together with its normalized form
+struct Term {
+    original: String,
+    clean: String,
+    /// The reported organ system (lowercased), only set for terms scored in the fuzzy matching
+    organ_system: Option<String>,
+}
+
+/// A MedDRA preferred or lowest level term with a normalized name and the preferred term code
+struct Pt {
+    name: String,
+    code: i32,
+    /// The lowest level term code, equal to the preferred term code for preferred terms
+    llt_code: i32,
+    /// Whether the lowest level term is current, 'Y' or 'N'
+    llt_currency: String,
+    /// 'PT' or 'LLT', the level the name was taken from
+    level: String,
+}
+
+/// How candidate terms are scored in the fuzzy matching. The score of a candidate is its Damerau
+/// Levenshtein distance, plus a penalty when none of the SOCs of its preferred term, primary or
+/// secondary, is the reported organ system.
+pub struct Scoring {
+    /// Candidates with a larger sift3 distance are not scored at all
+    pub sift3_cutoff: f32,
+    /// Only candidates scoring below this are accepted
+    pub max_score: usize,
+    /// Added to the distance of candidates outside the reported organ system
+    pub cross_soc_penalty: usize,
+    /// The number of threads the comparisons are spread over
+    pub threads: usize,
+    /// The number of candidate preferred terms stored per term for review
+    pub candidates: usize,
+}
+
+/// Which matching stages the terms take by their declared vocabulary. Every term is matched
+/// exactly first. Terms declared as SNOMED CT take their own path when the OMOP vocabulary is
+/// there, the others are free text and go through the CTCAE, token, compound and fuzzy matching.
+pub struct Routing {
+    /// Whether terms declared as SNOMED CT are matched through the OMOP vocabulary
+    pub snomed: bool,
+    /// Whether terms declared as MedDRA that fail the exact match are left unmapped instead of
+    /// being handled as free text, they are reported in ctgov.meddra_vocab_report either way
+    pub strict_meddra: bool,
+}
+
+impl Routing {
+    pub fn from_settings(settings: &Config) -> Self {
+        Self {
+            snomed: settings.get_bool("snomed").unwrap_or(false),
+            strict_meddra: settings.get_bool("meddra_strict_vocab").unwrap_or(false),
+        }
+    }
+
+    /// The declared vocabularies handled as free text, '' stands for none
+    fn free_text(&self) -> Vec<&'static str> {
+        let mut free_text = vec!["", "ctcae", "other"];
+        if !self.snomed {
+            free_text.push("snomed");
+        }
+        if !self.strict_meddra {
+            free_text.push("meddra");
+        }
+        free_text
+    }
+}
+
+/// A preferred term scored against a term in the fuzzy matching
+struct Candidate<'a> {
+    pt: &'a Pt,
+    sift3: f32,
+    distance: usize,
+    score: usize,
+}
+
+impl Scoring {
+    /// Reads the thresholds from the settings, the defaults are derived from meddra_precision
+    pub fn from_settings(settings: &Config) -> Self {
+        let precision = settings
+            .get_int("meddra_precision")
+            .expect("Could not read meddra_precision from settings file");
+        let sift3_cutoff = settings
+            .get_float("meddra_sift3_cutoff")
+            .unwrap_or(precision as f64 * 1.6) as f32;
+        let max_score = settings
+            .get_int("meddra_max_score")
+            .unwrap_or(precision * 5) as usize;
+        let cross_soc_penalty = settings
+            .get_int("meddra_cross_soc_penalty")
+            .unwrap_or((precision - 1) * 5) as usize;
+        let threads = match settings.get_int("threads") {
+            Ok(threads) => threads as usize,
+            Err(_) => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        let candidates = settings.get_int("meddra_candidates").unwrap_or(5) as usize;
+        Self {
+            sift3_cutoff,
+            max_score,
+            cross_soc_penalty,
+            threads: threads.max(1),
+            candidates: candidates.max(1),
+        }
+    }
+}
+
+pub async fn find_pts(
+    pool: &Pool,
+    scoring: &Scoring,
+    normalizer: &Normalizer,
+    tokens: &TokenMatcher,
+    ctcae: &HashMap<String, i32>,
+    curation: &Curation,
+    routing: &Routing,
+) -> Result<(), Box<dyn Error>> {
     info!("Starting MedDRA standardization");
     let client = pool.get().await?;
     let queries = Loader::read_queries_from("./sql/meddra_mapping.sql").unwrap();
@@ -19,115 +141,663 @@ pub async fn find_pts(pool: &Pool, precision: i64) -> Result<(), Box<dyn Error>>
     let q = fs::read_to_string("./sql/create_reported_events_table.sql")?;
     client.batch_execute(q.as_str()).await?;
 
-    let fast_precision: f32 = precision as f32 * 1.6;
-    let dam_lev_cutoff: usize = (precision * 5) as usize;
-    info!(
-        "Fuzzy search using a [{}] sift3 cutoff of and a [{}] damerau levenshtein max distance",
-        fast_precision, dam_lev_cutoff
-    );
-
-    execute("update_general_disorders", &client, &queries).await;
     execute("drop_table", &client, &queries).await;
     execute("create_table", &client, &queries).await;
+    execute("drop_compound_table", &client, &queries).await;
+    execute("create_compound_table", &client, &queries).await;
+    execute("drop_candidates_table", &client, &queries).await;
+    execute("create_candidates_table", &client, &queries).await;
 
-    let socs = query("find_all_socs", &client, &queries, &[]).await;
+    let free_text = routing.free_text();
 
-    let mut row_number: i8 = 0;
-    let total_rows = socs.len();
-    for soc_row in socs {
-        row_number += 1;
-        let soc: String = soc_row.get(0);
-        info!("[{}/{}] Mapping {}", row_number, total_rows, soc);
-        let terms_to_map = query("find_unknown_terms", &client, &queries, &[&soc]).await;
-        if terms_to_map.is_empty() {
-            continue;
+    let all_pts = to_pts(
+        query("find_all_pts", &client, &queries, &[]).await,
+        normalizer,
+    );
+    let pt_index: HashMap<&str, &Pt> = all_pts.iter().map(|pt| (pt.name.as_str(), pt)).collect();
+
+    match_exact(&client, &queries, normalizer, &pt_index).await;
+    execute_params("insert_pt_codes", &client, &queries, &[&free_text]).await;
+
+    // Grades are only stripped from the terms left, MedDRA names such as 'follicle centre
+    // lymphoma, follicular grade i, ii, iii' have matched exactly by now
+    strip_ctcae_grades(&client, &queries).await;
+    match_exact(&client, &queries, normalizer, &pt_index).await;
+    execute_params("insert_pt_codes", &client, &queries, &[&free_text]).await;
+
+    if routing.snomed {
+        match_snomed_terms(&client, &queries, normalizer, &all_pts).await;
+        execute_params("insert_pt_codes", &client, &queries, &[&free_text]).await;
+    }
+
+    if !ctcae.is_empty() {
+        match_ctcae_terms(&client, &queries, normalizer, ctcae, &all_pts, &free_text).await;
+        execute_params("insert_pt_codes", &client, &queries, &[&free_text]).await;
+    }
+
+    let token_index = index_tokens(tokens, &all_pts);
+    match_tokens(
+        &client,
+        &queries,
+        normalizer,
+        tokens,
+        &token_index,
+        &free_text,
+    )
+    .await;
+    execute_params("insert_pt_codes", &client, &queries, &[&free_text]).await;
+
+    let lookup = |clean: &str| -> Option<&Pt> {
+        match pt_index.get(clean) {
+            Some(pt) => Some(*pt),
+            None => token_index.get(&tokens.key(clean)).copied().flatten(),
         }
-        let pts = query("find_pts", &client, &queries, &[&soc]).await;
+    };
+    split_compound_terms(&client, &queries, normalizer, &free_text, lookup).await;
 
-        make_comparisons(terms_to_map, pts, fast_precision, dam_lev_cutoff, &pool).await?;
+    let mut pt_socs: HashMap<i32, HashSet<String>> = HashMap::new();
+    for row in query("find_pt_socs", &client, &queries, &[]).await {
+        pt_socs
+            .entry(row.get("pt_code"))
+            .or_default()
+            .insert(row.get("soc_name"));
     }
 
-    execute("catch_terms_including_the_word_or", &client, &queries).await;
-    execute("insert_wild_pt_code_1", &client, &queries).await;
-    execute("insert_wild_pt_code_2", &client, &queries).await;
-    let terms_to_map = query("find_all_unknown_terms", &client, &queries, &[]).await;
-    let pts = query("find_all_pts", &client, &queries, &[]).await;
+    let terms_to_map = to_terms(
+        query("find_unknown_terms", &client, &queries, &[&free_text]).await,
+        normalizer,
+    );
+    let terms_to_map = apply_curation(&client, &queries, curation, &all_pts, terms_to_map).await;
     info!(
-        "Going to check {} unmapped adverse events against all {} MedDRA preferred terms ... ",
+        "Scoring {} unmapped adverse events against all {} MedDRA terms using a [{}] sift3 cutoff, \
+        a [{}] maximum score and a [{}] penalty outside the reported organ system on {} threads",
         terms_to_map.len(),
-        pts.len()
+        all_pts.len(),
+        scoring.sift3_cutoff,
+        scoring.max_score,
+        scoring.cross_soc_penalty,
+        scoring.threads
     );
-    make_comparisons(terms_to_map, pts, 1.6, 5, &pool).await?;
+    make_comparisons(terms_to_map, &all_pts, &pt_socs, scoring, pool, &queries).await?;
 
     info!("Adding preferred term codes (pt_code) to reported events table");
-    execute("insert_wild_pt_code_1", &client, &queries).await;
-    execute("insert_wild_pt_code_2", &client, &queries).await;
+    execute_params("insert_pt_codes", &client, &queries, &[&free_text]).await;
+
+    report_vocab(&client, &queries).await;
+    roll_up_hierarchy(&client, &queries).await;
 
     Ok(())
 }
 
+/// Links every reported event to its preferred terms in ctgov.reported_event_pt, one per component
+/// for compound terms, and rolls those links up the hierarchy in ctgov.reported_event_hierarchy
+pub async fn roll_up_hierarchy(client: &Object<Manager>, queries: &HashMap<String, String>) {
+    info!("Linking the reported events to their preferred terms");
+    execute("drop_link_table", client, queries).await;
+    execute("create_link_table", client, queries).await;
+
+    info!("Rolling the reported events up the MedDRA hierarchy");
+    execute("drop_hierarchy_table", client, queries).await;
+    execute("create_hierarchy_table", client, queries).await;
+    execute("hierarchy_pt_index", client, queries).await;
+    execute("hierarchy_soc_index", client, queries).await;
+}
+
+fn to_terms(rows: Vec<Row>, normalizer: &Normalizer) -> Vec<Term> {
+    rows.iter()
+        .map(|r| {
+            let original: String = r.get("term");
+            let clean = normalizer.normalize(&original);
+            Term {
+                original,
+                clean,
+                organ_system: r.try_get("organ_system").ok(),
+            }
+        })
+        .filter(|t| !t.clean.is_empty())
+        .collect()
+}
+
+fn to_pts(rows: Vec<Row>, normalizer: &Normalizer) -> Vec<Pt> {
+    rows.iter()
+        .map(|r| {
+            let name: &str = r.get("pt_name");
+            Pt {
+                name: normalizer.normalize(name),
+                code: r.get("pt_code"),
+                llt_code: r.get("llt_code"),
+                llt_currency: r.get("llt_currency"),
+                level: r.get("match_level"),
+            }
+        })
+        .filter(|pt| !pt.name.is_empty())
+        .collect()
+}
+
+/// Matches the unmapped terms whose normalized form is a MedDRA preferred or lowest level term
+async fn match_exact(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    normalizer: &Normalizer,
+    pt_index: &HashMap<&str, &Pt>,
+) {
+    info!("Finding exact matches after normalization");
+    let unmapped = to_terms(
+        query("find_unmapped_terms", client, queries, &[]).await,
+        normalizer,
+    );
+    let mappings: Vec<(Term, &Pt)> = unmapped
+        .into_iter()
+        .filter_map(|t| pt_index.get(t.clean.as_str()).map(|pt| (t, *pt)))
+        .collect();
+    insert_mappings(client, queries, "exact", &mappings).await;
+}
+
+/// Moves CTCAE grades such as in 'neutropenia grade 3' from the term to the ctcae_grade column
+async fn strip_ctcae_grades(client: &Object<Manager>, queries: &HashMap<String, String>) {
+    let unmapped = query("find_unmapped_terms", client, queries, &[]).await;
+    let mut graded: (Vec<String>, Vec<String>, Vec<i16>) = (vec![], vec![], vec![]);
+    for row in unmapped {
+        let original: String = row.get("term");
+        if let (term, Some(grade)) = parse_grade(&original) {
+            graded.0.push(original);
+            graded.1.push(term);
+            graded.2.push(grade);
+        }
+    }
+    info!("Found a CTCAE grade in {} terms", graded.0.len());
+    execute_params(
+        "update_ctcae_grades",
+        client,
+        queries,
+        &[&graded.0, &graded.1, &graded.2],
+    )
+    .await;
+}
+
+/// Lists the declared vocabulary of every term with how it was matched in ctgov.meddra_vocab_report,
+/// flagging terms declared as MedDRA or CTCAE that turned out not to be
+async fn report_vocab(client: &Object<Manager>, queries: &HashMap<String, String>) {
+    info!("Checking the declared vocabularies");
+    execute("drop_vocab_report", client, queries).await;
+    execute("create_vocab_report", client, queries).await;
+    for row in query("count_vocab_findings", client, queries, &[]).await {
+        let vocab: &str = row.get("declared_vocab");
+        let finding: &str = row.get("finding");
+        let terms: i64 = row.get("terms");
+        let events: i64 = row.get("events");
+        info!(
+            "{} terms ({} events) declared as {} are {}",
+            terms, events, vocab, finding
+        );
+    }
+}
+
+/// Maps free text terms and terms carrying a grade to the MedDRA lowest level term given by the
+/// CTCAE correspondence file
+async fn match_ctcae_terms(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    normalizer: &Normalizer,
+    ctcae: &HashMap<String, i32>,
+    pts: &[Pt],
+    free_text: &[&str],
+) {
+    info!("Finding matches using the CTCAE to MedDRA correspondence");
+    let llt_index: HashMap<i32, &Pt> = pts.iter().map(|pt| (pt.llt_code, pt)).collect();
+    let unmapped = to_terms(
+        query("find_unmapped_ctcae_terms", client, queries, &[&free_text]).await,
+        normalizer,
+    );
+    let mappings: Vec<(Term, &Pt)> = unmapped
+        .into_iter()
+        .filter_map(|t| {
+            ctcae
+                .get(&t.clean)
+                .and_then(|code| llt_index.get(code))
+                .map(|pt| (t, *pt))
+        })
+        .collect();
+    insert_mappings(client, queries, "ctcae", &mappings).await;
+}
+
+/// Maps terms declared as SNOMED CT that name a SNOMED CT concept to the MedDRA preferred term the
+/// OMOP vocabulary holds equivalent, when there is only one
+async fn match_snomed_terms(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    normalizer: &Normalizer,
+    pts: &[Pt],
+) {
+    info!("Finding matches through SNOMED CT for terms declared as SNOMED CT");
+    let llt_index: HashMap<i32, &Pt> = pts.iter().map(|pt| (pt.llt_code, pt)).collect();
+    let mappings: Vec<(Term, &Pt)> = query("find_snomed_matches", client, queries, &[])
+        .await
+        .iter()
+        .filter_map(|row| {
+            let original: String = row.get("term");
+            let code: i32 = row.get("pt_code");
+            llt_index.get(&code).map(|pt| {
+                let term = Term {
+                    clean: normalizer.normalize(&original),
+                    original,
+                    organ_system: None,
+                };
+                (term, *pt)
+            })
+        })
+        .collect();
+    insert_mappings(client, queries, "snomed", &mappings).await;
+}
+
+/// Maps the terms reviewed with the review command to the LLT picked by the reviewer. Returns the
+/// terms that were not reviewed, rejected terms are left unmapped and skip the fuzzy matching.
+async fn apply_curation(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    curation: &Curation,
+    pts: &[Pt],
+    terms: Vec<Term>,
+) -> Vec<Term> {
+    if curation.len() == 0 {
+        return terms;
+    }
+    info!(
+        "Applying the curated MedDRA mappings from {}",
+        curation.path
+    );
+    let llt_index: HashMap<i32, &Pt> = pts.iter().map(|pt| (pt.llt_code, pt)).collect();
+    let mut mappings: Vec<(Term, &Pt)> = Vec::new();
+    let mut remaining: Vec<Term> = Vec::new();
+    let mut rejected = 0;
+    for term in terms {
+        match curation.decision(&term.original, term.organ_system.as_deref()) {
+            Some(Some(code)) => match llt_index.get(&code) {
+                Some(pt) => mappings.push((term, *pt)),
+                None => {
+                    warn!(
+                        "Curated LLT {} of {:?} is not in MedDRA",
+                        code, term.original
+                    );
+                    remaining.push(term);
+                }
+            },
+            Some(None) => rejected += 1,
+            None => remaining.push(term),
+        }
+    }
+    info!("Skipping {} rejected terms", rejected);
+    insert_mappings(client, queries, "curated", &mappings).await;
+    remaining
+}
+
+/// Splits a term on 'or', 'and' and slashes, e.g. 'nausea/vomiting'. Commas are left alone, they
+/// are part of terms such as 'infection, viral'.
+fn split_compound(term: &str) -> Vec<&str> {
+    term.split('/')
+        .flat_map(|part| part.split(" or "))
+        .flat_map(|part| part.split(" and "))
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// Maps compound terms such as 'nausea/vomiting' or 'rash and pruritus' to a preferred term per
+/// component, but only when every component matches a MedDRA term exactly or on its words.
+async fn split_compound_terms<'a>(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    normalizer: &Normalizer,
+    free_text: &[&str],
+    lookup: impl Fn(&str) -> Option<&'a Pt>,
+) {
+    info!("Splitting compound terms into their components");
+    let unmapped = query(
+        "find_unmapped_free_text_terms",
+        client,
+        queries,
+        &[&free_text],
+    )
+    .await;
+    let mut components: Vec<(String, String, &Pt)> = Vec::new();
+    for row in unmapped {
+        let term: String = row.get("term");
+        let parts = split_compound(&term);
+        if parts.len() < 2 {
+            continue;
+        }
+        let matched: Vec<(String, &Pt)> = parts
+            .iter()
+            .filter_map(|part| lookup(&normalizer.normalize(part)).map(|pt| (part.to_string(), pt)))
+            .collect();
+        if matched.len() == parts.len() {
+            for (component, pt) in matched {
+                components.push((term.clone(), component, pt));
+            }
+        }
+    }
+
+    let originals: Vec<&str> = components.iter().map(|c| c.0.as_str()).collect();
+    let parts: Vec<&str> = components.iter().map(|c| c.1.as_str()).collect();
+    let standards: Vec<&str> = components.iter().map(|c| c.2.name.as_str()).collect();
+    let pt_codes: Vec<i32> = components.iter().map(|c| c.2.code).collect();
+    let llt_codes: Vec<i32> = components.iter().map(|c| c.2.llt_code).collect();
+    let currencies: Vec<&str> = components
+        .iter()
+        .map(|c| c.2.llt_currency.as_str())
+        .collect();
+    let levels: Vec<&str> = components.iter().map(|c| c.2.level.as_str()).collect();
+    execute_params(
+        "insert_compound_mappings",
+        client,
+        queries,
+        &[
+            &originals,
+            &parts,
+            &standards,
+            &pt_codes,
+            &llt_codes,
+            &currencies,
+            &levels,
+        ],
+    )
+    .await;
+    execute_params("mark_compound_terms", client, queries, &[&free_text]).await;
+    info!("Stored {} compound term components", components.len());
+}
+
+/// Indexes the MedDRA terms on their word key, keys shared by different preferred terms map to None
+fn index_tokens<'a>(tokens: &TokenMatcher, pts: &'a [Pt]) -> HashMap<String, Option<&'a Pt>> {
+    let mut index: HashMap<String, Option<&Pt>> = HashMap::new();
+    for pt in pts {
+        index
+            .entry(tokens.key(&pt.name))
+            .and_modify(|found| {
+                if found.is_some_and(|f| f.code != pt.code) {
+                    *found = None;
+                }
+            })
+            .or_insert(Some(pt));
+    }
+    index
+}
+
+/// Matches terms that contain the same words as a MedDRA term in any order, after expanding
+/// abbreviations and unifying spelling. Keys shared by different preferred terms are skipped.
+async fn match_tokens(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    normalizer: &Normalizer,
+    tokens: &TokenMatcher,
+    index: &HashMap<String, Option<&Pt>>,
+    free_text: &[&str],
+) {
+    info!("Finding matches ignoring word order, abbreviations and spelling");
+    let unmapped = to_terms(
+        query(
+            "find_unmapped_free_text_terms",
+            client,
+            queries,
+            &[&free_text],
+        )
+        .await,
+        normalizer,
+    );
+    let mappings: Vec<(Term, &Pt)> = unmapped
+        .into_iter()
+        .filter_map(|t| match index.get(&tokens.key(&t.clean)) {
+            Some(Some(pt)) => Some((t, *pt)),
+            _ => None,
+        })
+        .collect();
+    insert_mappings(client, queries, "token", &mappings).await;
+}
+
+async fn insert_mappings(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    method: &str,
+    mappings: &[(Term, &Pt)],
+) {
+    let originals: Vec<&str> = mappings.iter().map(|m| m.0.original.as_str()).collect();
+    let organ_systems: Vec<Option<&str>> = mappings
+        .iter()
+        .map(|m| m.0.organ_system.as_deref())
+        .collect();
+    let standards: Vec<&str> = mappings.iter().map(|m| m.1.name.as_str()).collect();
+    let pt_codes: Vec<i32> = mappings.iter().map(|m| m.1.code).collect();
+    let llt_codes: Vec<i32> = mappings.iter().map(|m| m.1.llt_code).collect();
+    let currencies: Vec<&str> = mappings.iter().map(|m| m.1.llt_currency.as_str()).collect();
+    let levels: Vec<&str> = mappings.iter().map(|m| m.1.level.as_str()).collect();
+    execute_params(
+        "insert_mappings",
+        client,
+        queries,
+        &[
+            &originals,
+            &organ_systems,
+            &standards,
+            &pt_codes,
+            &llt_codes,
+            &currencies,
+            &levels,
+            &method,
+        ],
+    )
+    .await;
+    info!("Stored {} {} term mappings", mappings.len(), method);
+}
+
 async fn make_comparisons(
-    terms_to_map: Vec<Row>,
-    pts: Vec<Row>,
-    fast_precision: f32,
-    dam_lev_cutoff: usize,
+    terms_to_map: Vec<Term>,
+    pts: &[Pt],
+    pt_socs: &HashMap<i32, HashSet<String>>,
+    scoring: &Scoring,
     pool: &Pool,
+    queries: &HashMap<String, String>,
 ) -> Result<(), Box<dyn Error>> {
     let client = pool.get().await?;
-    let mut insert_values: String = String::from("");
-    let mut pb = ProgressBar::new((terms_to_map.len() / 100) as u64);
-    pb.set_width(Some(80));
-    let mut counter: i8 = 0;
-    for term_row in &terms_to_map {
-        counter += 1;
-        if counter % 100 == 0 {
-            pb.inc();
-        }
-        let mut comparisons: BTreeMap<usize, (&str, i32)> = BTreeMap::new();
-        let term: &str = term_row.get(0);
-        let term_clean = term
-            .replace("other", "")
-            .replace(" nos", "")
-            .replace(" any", "")
-            .replace("specify", "")
-            .replace("  ", " ");
-        for pt_row in &pts {
-            let pt = pt_row.get("pt_name");
-            let pt_code: i32 = pt_row.get("pt_code");
-            let fast_distance = distance::sift3(&term_clean, pt);
-            if fast_distance < fast_precision {
-                let distance = distance::damerau_levenshtein(&term_clean, pt);
-                if distance < dam_lev_cutoff {
-                    comparisons.insert(distance, (pt, pt_code));
-                }
+    let total = terms_to_map.len();
+    let progress = Progress::new(total);
+
+    // The comparisons are CPU bound, so they run on their own threads, each taking an equal part
+    // of the terms, while block_in_place lets the runtime move its other tasks elsewhere.
+    let chunk_size = total.div_ceil(scoring.threads).max(1);
+    let ranked: Vec<Vec<Candidate>> = task::block_in_place(|| {
+        thread::scope(|scope| {
+            let handles: Vec<_> = terms_to_map
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let progress = &progress;
+                    scope.spawn(move || {
+                        let mut found = Vec::with_capacity(chunk.len());
+                        for term in chunk {
+                            found.push(rank_candidates(term, pts, pt_socs, scoring));
+                            progress.inc();
+                        }
+                        found
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    });
+    progress.finish();
+
+    insert_candidates(&client, queries, &terms_to_map, &ranked).await;
+
+    let mut mappings: Vec<(Term, &Pt)> = Vec::new();
+    for (term, candidates) in terms_to_map.into_iter().zip(ranked) {
+        match candidates.first() {
+            Some(best) if best.score < scoring.max_score => {
+                debug!(
+                    "matched  {0: <25}  to  {1: <25}  {2: <20}",
+                    term.original, best.pt.name, best.score
+                );
+                mappings.push((term, best.pt));
             }
-        }
-        if !comparisons.is_empty() {
-            let pt = comparisons.iter().next().unwrap();
-            debug!(
-                "matched  {0: <25}  to  {1: <25}  {2: <20}",
-                term, pt.1 .0, pt.0
-            );
-            // quotation marks must be escaped
-            let fixed_original = term.replace("'", "''");
-            let fixed_standard = pt.1 .0.replace("'", "''");
-            let value = format!(
-                "(\'{}\', \'{}\', {}),",
-                fixed_original, fixed_standard, pt.1 .1
-            );
-            insert_values.push_str(&*value);
+            _ => {}
         }
     }
-    // remove trailing comma
-    insert_values.pop();
-    let q = format!(
-        "INSERT INTO ctgov.rg_meddra_map(original, standard, pt_code) VALUES {};",
-        insert_values
-    );
-    pb.finish();
-    println!();
-    let r1 = client.execute(q.as_str(), &[]).await.unwrap();
-    info!("Mapped {} out of {} unknown terms", r1, terms_to_map.len());
+    insert_mappings(&client, queries, "fuzzy", &mappings).await;
+    info!("Mapped {} out of {} unknown terms", mappings.len(), total);
 
     Ok(())
 }
+
+/// Stores the ranked candidates of every scored term in ctgov.rg_meddra_candidates, flagging the
+/// terms whose two best candidates have the same score
+async fn insert_candidates(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    terms: &[Term],
+    ranked: &[Vec<Candidate<'_>>],
+) {
+    let rows: Vec<(&Term, usize, &Candidate, bool)> = terms
+        .iter()
+        .zip(ranked)
+        .flat_map(|(term, candidates)| {
+            let tied = candidates.len() > 1 && candidates[0].score == candidates[1].score;
+            candidates
+                .iter()
+                .enumerate()
+                .map(move |(rank, candidate)| (term, rank + 1, candidate, tied))
+        })
+        .collect();
+    let originals: Vec<&str> = rows.iter().map(|r| r.0.original.as_str()).collect();
+    let organ_systems: Vec<Option<&str>> =
+        rows.iter().map(|r| r.0.organ_system.as_deref()).collect();
+    let ranks: Vec<i16> = rows.iter().map(|r| r.1 as i16).collect();
+    let standards: Vec<&str> = rows.iter().map(|r| r.2.pt.name.as_str()).collect();
+    let pt_codes: Vec<i32> = rows.iter().map(|r| r.2.pt.code).collect();
+    let llt_codes: Vec<i32> = rows.iter().map(|r| r.2.pt.llt_code).collect();
+    let levels: Vec<&str> = rows.iter().map(|r| r.2.pt.level.as_str()).collect();
+    let sift3: Vec<f32> = rows.iter().map(|r| r.2.sift3).collect();
+    let distances: Vec<i32> = rows.iter().map(|r| r.2.distance as i32).collect();
+    let scores: Vec<i32> = rows.iter().map(|r| r.2.score as i32).collect();
+    let tied: Vec<bool> = rows.iter().map(|r| r.3).collect();
+    execute_params(
+        "insert_candidates",
+        client,
+        queries,
+        &[
+            &originals,
+            &organ_systems,
+            &ranks,
+            &standards,
+            &pt_codes,
+            &llt_codes,
+            &levels,
+            &sift3,
+            &distances,
+            &scores,
+            &tied,
+        ],
+    )
+    .await;
+    let ties = ranked
+        .iter()
+        .filter(|c| c.len() > 1 && c[0].score == c[1].score)
+        .count();
+    info!(
+        "Stored {} candidates, the two best candidates tie for {} terms",
+        rows.len(),
+        ties
+    );
+}
+
+/// The best scoring candidates within the sift3 cutoff, lowest score first and one per preferred
+/// term. Candidates with equal scores keep the order of the MedDRA terms.
+fn rank_candidates<'a>(
+    term: &Term,
+    pts: &'a [Pt],
+    pt_socs: &HashMap<i32, HashSet<String>>,
+    scoring: &Scoring,
+) -> Vec<Candidate<'a>> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for pt in pts {
+        let sift3 = distance::sift3(&term.clean, &pt.name);
+        if sift3 >= scoring.sift3_cutoff {
+            continue;
+        }
+        let in_soc = match &term.organ_system {
+            Some(soc) => pt_socs.get(&pt.code).is_some_and(|socs| socs.contains(soc)),
+            None => false,
+        };
+        let distance = distance::damerau_levenshtein(&term.clean, &pt.name);
+        let score = if in_soc {
+            distance
+        } else {
+            distance + scoring.cross_soc_penalty
+        };
+        candidates.push(Candidate {
+            pt,
+            sift3,
+            distance,
+            score,
+        });
+    }
+    candidates.sort_by_key(|c| c.score);
+    let mut seen: HashSet<i32> = HashSet::new();
+    candidates.retain(|c| seen.insert(c.pt.code));
+    candidates.truncate(scoring.candidates);
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_compound_terms() {
+        assert_eq!(
+            split_compound("nausea/vomiting"),
+            vec!["nausea", "vomiting"]
+        );
+        assert_eq!(
+            split_compound("rash and pruritus"),
+            vec!["rash", "pruritus"]
+        );
+        assert_eq!(
+            split_compound("nausea or vomiting/diarrhoea"),
+            vec!["nausea", "vomiting", "diarrhoea"]
+        );
+        assert_eq!(split_compound("infection, viral"), vec!["infection, viral"]);
+        assert_eq!(split_compound("headache"), vec!["headache"]);
+    }
+
+    #[test]
+    fn ranks_one_candidate_per_pt() {
+        let pt = |name: &str, code: i32, llt_code: i32| Pt {
+            name: name.to_string(),
+            code,
+            llt_code,
+            llt_currency: String::from("Y"),
+            level: String::from("PT"),
+        };
+        let pts = vec![
+            pt("rash", 1, 1),
+            pt("rashes", 1, 2),
+            pt("rush", 2, 2),
+            pt("nausea", 3, 3),
+        ];
+        let term = Term {
+            original: String::from("rsh"),
+            clean: String::from("rsh"),
+            organ_system: None,
+        };
+        let scoring = Scoring {
+            sift3_cutoff: 4.0,
+            max_score: 5,
+            cross_soc_penalty: 0,
+            threads: 1,
+            candidates: 5,
+        };
+        let ranked = rank_candidates(&term, &pts, &HashMap::new(), &scoring);
+        let codes: Vec<(i32, usize)> = ranked.iter().map(|c| (c.pt.code, c.score)).collect();
+        assert_eq!(codes, vec![(1, 1), (2, 1)]);
+    }
+}
@@ -0,0 +1,54 @@
+use std::error::Error;
+
+use deadpool_postgres::Pool;
+use log::{info, warn};
+use rawsql::Loader;
+
+use crate::db::{execute, query};
+use crate::meddra_mapping::roll_up_hierarchy;
+use crate::run_log::RunLog;
+
+/// Moves the MedDRA codes of an earlier run to the MedDRA release currently in the meddra schema,
+/// following LLTs that moved to another PT. Every event and what happened to it is listed in
+/// ctgov.meddra_migration.
+pub async fn migrate(pool: &Pool, run_log: &RunLog, version: &str) -> Result<(), Box<dyn Error>> {
+    let previous = run_log
+        .previous(pool, "meddra_version")
+        .await
+        .unwrap_or_else(|| String::from("unknown"));
+    info!(
+        "Migrating MedDRA codes from version {} to {}",
+        previous, version
+    );
+    if previous == version {
+        warn!(
+            "The reported events were already mapped with MedDRA {}",
+            version
+        );
+    }
+
+    let client = pool.get().await?;
+    let queries = Loader::read_queries_from("./sql/meddra_migration.sql").unwrap();
+    execute("drop_migration_table", &client, &queries).await;
+    execute("create_migration_table", &client, &queries).await;
+    for row in query("count_by_status", &client, &queries, &[]).await {
+        let status: &str = row.get("status");
+        let events: i64 = row.get("events");
+        info!("{} events {}", events, status);
+    }
+    execute("update_reported_events", &client, &queries).await;
+    execute("update_compound_map", &client, &queries).await;
+
+    let mapping_queries = Loader::read_queries_from("./sql/meddra_mapping.sql").unwrap();
+    roll_up_hierarchy(&client, &mapping_queries).await;
+
+    run_log
+        .record(
+            pool,
+            "meddra_migration",
+            &format!("{} -> {}", previous, version),
+        )
+        .await;
+    run_log.record(pool, "meddra_version", version).await;
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use config::Config;
+use deadpool_postgres::Pool;
+use log::{info, warn};
+use rawsql::Loader;
+
+use crate::db::query;
+
+const HEADER: &str = "term\torgan_system\tdecision\tllt_code";
+
+/// The decisions of the MedDRA review, a tab separated file with the columns term, organ_system,
+/// decision and llt_code. Rejected terms have no llt_code and an empty organ_system matches any.
+pub struct Curation {
+    pub path: String,
+    decisions: HashMap<(String, String), Option<i32>>,
+}
+
+impl Curation {
+    /// Reads the file named by meddra_curation, a missing file holds no decisions yet
+    pub fn from_settings(settings: &Config) -> Self {
+        let path = settings
+            .get_string("meddra_curation")
+            .unwrap_or_else(|_| String::from("resources/meddra_curation.tsv"));
+        let content = if Path::new(&path).exists() {
+            fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Error reading curation {}: {}", path, e))
+        } else {
+            String::new()
+        };
+        let curation = Self::parse(path, &content);
+        info!(
+            "Read {} curated MedDRA terms from {}",
+            curation.len(),
+            curation.path
+        );
+        curation
+    }
+
+    fn parse(path: String, content: &str) -> Self {
+        let mut decisions = HashMap::new();
+        for line in content.lines().skip(1).filter(|l| !l.trim().is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 4 {
+                panic!("Invalid line in {}: {:?}", path, line);
+            }
+            let llt_code = match fields[3].trim() {
+                "" => None,
+                code => Some(
+                    code.parse()
+                        .unwrap_or_else(|e| panic!("Invalid LLT code in {}: {}", path, e)),
+                ),
+            };
+            // Later decisions on the same term replace earlier ones
+            decisions.insert((fields[0].to_string(), fields[1].to_string()), llt_code);
+        }
+        Self { path, decisions }
+    }
+
+    /// Some(llt_code) for a curated term, Some(None) for a rejected one and None if not reviewed
+    pub fn decision(&self, term: &str, organ_system: Option<&str>) -> Option<Option<i32>> {
+        let key = |soc: &str| (term.to_string(), soc.to_string());
+        organ_system
+            .and_then(|soc| self.decisions.get(&key(soc)))
+            .or_else(|| self.decisions.get(&key("")))
+            .copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.decisions.len()
+    }
+
+    fn contains(&self, term: &str, organ_system: &str) -> bool {
+        self.decisions
+            .contains_key(&(term.to_string(), organ_system.to_string()))
+    }
+}
+
+struct Candidate {
+    standard: String,
+    pt_code: i32,
+    llt_code: i32,
+    match_level: String,
+    distance: i32,
+    score: i32,
+}
+
+/// Walks through the low confidence terms in ctgov.rg_meddra_candidates that have not been reviewed
+/// yet, those with the most events first, and appends every decision to the curation file right
+/// away. Low confidence terms have two tied best candidates or a best score within the margin of
+/// the maximum score.
+pub async fn review(
+    pool: &Pool,
+    curation: &Curation,
+    max_score: usize,
+    margin: usize,
+) -> Result<(), Box<dyn Error>> {
+    let client = pool.get().await?;
+    let queries = Loader::read_queries_from("./sql/meddra_review.sql").unwrap();
+
+    let mut candidates: HashMap<(String, String), Vec<Candidate>> = HashMap::new();
+    for row in query("find_candidates", &client, &queries, &[]).await {
+        candidates
+            .entry((row.get("term"), row.get("organ_system")))
+            .or_default()
+            .push(Candidate {
+                standard: row.get("standard"),
+                pt_code: row.get("pt_code"),
+                llt_code: row.get("llt_code"),
+                match_level: row.get("match_level"),
+                distance: row.get("distance"),
+                score: row.get("score"),
+            });
+    }
+    let lowest = max_score.saturating_sub(margin) as i32;
+    let highest = (max_score + margin) as i32;
+    let terms: Vec<(String, String, i64)> =
+        query("find_review_terms", &client, &queries, &[&lowest, &highest])
+            .await
+            .iter()
+            .map(|r| (r.get("term"), r.get("organ_system"), r.get("events")))
+            .filter(|t: &(String, String, i64)| !curation.contains(&t.0, &t.1))
+            .collect();
+    // Tabs and line breaks would break the curation file, such terms cannot be curated
+    let (terms, unstorable): (Vec<_>, Vec<_>) = terms
+        .into_iter()
+        .partition(|(term, organ_system, _)| storable(term) && storable(organ_system));
+    if !unstorable.is_empty() {
+        warn!(
+            "Skipping {} terms containing a tab or line break",
+            unstorable.len()
+        );
+    }
+    info!("{} terms left to review", terms.len());
+
+    let new_file = !Path::new(&curation.path).exists();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&curation.path)?;
+    if new_file {
+        writeln!(file, "{}", HEADER)?;
+    }
+
+    let mut reviewed = 0;
+    let mut lines = io::stdin().lock().lines();
+    for (i, (term, organ_system, events)) in terms.iter().enumerate() {
+        let options = &candidates[&(term.clone(), organ_system.clone())];
+        println!();
+        println!(
+            "[{}/{}] {:?} in {:?}, {} events",
+            i + 1,
+            terms.len(),
+            term,
+            organ_system,
+            events
+        );
+        for (rank, c) in options.iter().enumerate() {
+            let mapped = rank == 0 && (c.score as usize) < max_score;
+            println!(
+                "  {} {:<2} {:<50} PT {:<9} LLT {:<9} {:<3} distance {:<3} score {}",
+                if mapped { '*' } else { ' ' },
+                rank + 1,
+                c.standard,
+                c.pt_code,
+                c.llt_code,
+                c.match_level,
+                c.distance,
+                c.score
+            );
+        }
+        let decision = loop {
+            print!(
+                "[a]ccept the first, [1-{}] pick, [r]eject, [s]kip or [q]uit: ",
+                options.len()
+            );
+            io::stdout().flush()?;
+            let answer = match lines.next() {
+                Some(line) => line?.trim().to_lowercase(),
+                None => String::from("q"),
+            };
+            match answer.as_str() {
+                "a" => break Some(("accepted", Some(options[0].llt_code))),
+                "r" => break Some(("rejected", None)),
+                "s" => break None,
+                "q" => {
+                    info!(
+                        "Reviewed {} terms, decisions are in {}",
+                        reviewed, curation.path
+                    );
+                    return Ok(());
+                }
+                n => match n.parse::<usize>() {
+                    Ok(n) if (1..=options.len()).contains(&n) => {
+                        break Some(("picked", Some(options[n - 1].llt_code)))
+                    }
+                    _ => println!("Unknown answer {:?}", n),
+                },
+            }
+        };
+        if let Some((decision, llt_code)) = decision {
+            let llt_code = llt_code.map(|c| c.to_string()).unwrap_or_default();
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}",
+                term, organ_system, decision, llt_code
+            )?;
+            reviewed += 1;
+        }
+    }
+    info!(
+        "Reviewed {} terms, decisions are in {}",
+        reviewed, curation.path
+    );
+    Ok(())
+}
+
+/// Whether a term or organ system can be written to the tab separated curation file
+fn storable(field: &str) -> bool {
+    !field.contains(['\t', '\n', '\r'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curation(lines: &[&str]) -> Curation {
+        let content = format!("{}\n{}\n", HEADER, lines.join("\n"));
+        Curation::parse(String::from("curation.tsv"), &content)
+    }
+
+    #[test]
+    fn reads_decisions() {
+        let c = curation(&[
+            "hep tox\thepatobiliary disorders\taccepted\t10019851",
+            "",
+            "weird feeling\t\trejected\t",
+        ]);
+        assert_eq!(c.len(), 2);
+        assert_eq!(
+            c.decision("hep tox", Some("hepatobiliary disorders")),
+            Some(Some(10019851))
+        );
+        assert_eq!(c.decision("weird feeling", None), Some(None));
+        assert_eq!(c.decision("nausea", None), None);
+    }
+
+    #[test]
+    fn later_decisions_win() {
+        let c = curation(&[
+            "hep tox\t\taccepted\t10019851",
+            "hep tox\t\tpicked\t10019837",
+        ]);
+        assert_eq!(c.decision("hep tox", None), Some(Some(10019837)));
+    }
+
+    #[test]
+    fn falls_back_to_any_organ_system() {
+        let c = curation(&[
+            "hep tox\t\taccepted\t10019851",
+            "hep tox\tinvestigations\tpicked\t10019837",
+        ]);
+        assert_eq!(
+            c.decision("hep tox", Some("investigations")),
+            Some(Some(10019837))
+        );
+        assert_eq!(
+            c.decision("hep tox", Some("hepatobiliary disorders")),
+            Some(Some(10019851))
+        );
+        assert_eq!(c.decision("hep tox", None), Some(Some(10019851)));
+    }
+
+    #[test]
+    fn refuses_tabs() {
+        assert!(storable("hep tox"));
+        assert!(!storable("hep\ttox"));
+        assert!(!storable("hep\ntox"));
+    }
+}
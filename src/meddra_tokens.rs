@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs;
+
+use config::Config;
+use log::info;
+
+use crate::normalize::Normalizer;
+
+/// Turns normalized terms into a key that ignores word order, abbreviations and British or
+/// American spelling, so 'increased alt' and 'alanine aminotransferase increased' get the same key.
+#[derive(Debug, Default)]
+pub struct TokenMatcher {
+    abbreviations: HashMap<String, Vec<String>>,
+    /// Abbreviations with more than one meaning, such as 'pe' or 'ten', only expanded when they
+    /// make up the whole term
+    whole_term_abbreviations: HashMap<String, Vec<String>>,
+    spellings: HashMap<String, String>,
+}
+
+impl TokenMatcher {
+    /// Reads the dictionaries named by meddra_abbreviations and meddra_spellings, both tab separated
+    /// files with a header. Both sides are normalized with the MedDRA normalizer. Abbreviations with
+    /// 'whole' in a third column are only expanded when they make up the whole term.
+    pub fn from_settings(settings: &Config, normalizer: &Normalizer) -> Self {
+        let mut matcher = Self::default();
+        let abbreviations = settings
+            .get_string("meddra_abbreviations")
+            .unwrap_or_else(|_| String::from("resources/meddra_abbreviations.tsv"));
+        for (abbreviation, expansion, whole_term) in read_pairs(&abbreviations, normalizer) {
+            if whole_term {
+                matcher.add_whole_term_abbreviation(&abbreviation, &expansion);
+            } else {
+                matcher.add_abbreviation(&abbreviation, &expansion);
+            }
+        }
+        let spellings = settings
+            .get_string("meddra_spellings")
+            .unwrap_or_else(|_| String::from("resources/meddra_spellings.tsv"));
+        for (variant, spelling, _) in read_pairs(&spellings, normalizer) {
+            matcher.add_spelling(&variant, &spelling);
+        }
+        matcher
+    }
+
+    pub fn add_abbreviation(&mut self, abbreviation: &str, expansion: &str) {
+        let words = expansion.split_whitespace().map(String::from).collect();
+        self.abbreviations.insert(abbreviation.to_string(), words);
+    }
+
+    pub fn add_whole_term_abbreviation(&mut self, abbreviation: &str, expansion: &str) {
+        let words = expansion.split_whitespace().map(String::from).collect();
+        self.whole_term_abbreviations
+            .insert(abbreviation.to_string(), words);
+    }
+
+    pub fn add_spelling(&mut self, variant: &str, spelling: &str) {
+        self.spellings
+            .insert(variant.to_string(), spelling.to_string());
+    }
+
+    /// The sorted words of a normalized term after expanding abbreviations and unifying spelling
+    pub fn key(&self, clean: &str) -> String {
+        let mut words: Vec<&str> = Vec::new();
+        let whole_term = self.whole_term_abbreviations.get(clean.trim());
+        if let Some(expansion) = whole_term {
+            words.extend(expansion.iter().map(|w| self.spelling(w)));
+        }
+        for word in clean
+            .split_whitespace()
+            .take_while(|_| whole_term.is_none())
+        {
+            match self.abbreviations.get(word) {
+                Some(expansion) => words.extend(expansion.iter().map(|w| self.spelling(w))),
+                None => words.push(self.spelling(word)),
+            }
+        }
+        words.sort_unstable();
+        words.dedup();
+        words.join(" ")
+    }
+
+    fn spelling<'a>(&'a self, word: &'a str) -> &'a str {
+        self.spellings.get(word).map(|s| s.as_str()).unwrap_or(word)
+    }
+}
+
+/// Reads the first two columns of a dictionary, normalized, and whether the third says 'whole'
+fn read_pairs(path: &str, normalizer: &Normalizer) -> Vec<(String, String, bool)> {
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Error reading dictionary {}: {}", path, e));
+    let pairs: Vec<(String, String, bool)> = content
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 2 {
+                panic!("Invalid line in {}: {:?}", path, line);
+            }
+            let whole_term = fields.get(2).is_some_and(|f| f.trim() == "whole");
+            (
+                normalizer.normalize(fields[0]),
+                normalizer.normalize(fields[1]),
+                whole_term,
+            )
+        })
+        .collect();
+    info!("Read {} entries from {}", pairs.len(), path);
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher() -> TokenMatcher {
+        let mut matcher = TokenMatcher::default();
+        matcher.add_abbreviation("alt", "alanine aminotransferase");
+        matcher.add_abbreviation("urti", "upper respiratory tract infection");
+        matcher.add_whole_term_abbreviation("ten", "toxic epidermal necrolysis");
+        matcher.add_whole_term_abbreviation("pe", "pulmonary embolism");
+        matcher.add_spelling("diarrhea", "diarrhoea");
+        matcher.add_spelling("anemia", "anaemia");
+        matcher
+    }
+
+    #[test]
+    fn ignores_word_order() {
+        let matcher = matcher();
+        assert_eq!(
+            matcher.key("increased alanine aminotransferase"),
+            matcher.key("alanine aminotransferase increased")
+        );
+    }
+
+    #[test]
+    fn expands_abbreviations() {
+        let matcher = matcher();
+        assert_eq!(
+            matcher.key("increased alt"),
+            "alanine aminotransferase increased"
+        );
+        assert_eq!(
+            matcher.key("urti"),
+            matcher.key("upper respiratory tract infection")
+        );
+    }
+
+    #[test]
+    fn unifies_spelling() {
+        let matcher = matcher();
+        assert_eq!(matcher.key("diarrhea"), "diarrhoea");
+        assert_eq!(
+            matcher.key("anemia aggravated"),
+            matcher.key("aggravated anaemia")
+        );
+    }
+
+    #[test]
+    fn expands_ambiguous_abbreviations_only_as_whole_term() {
+        let matcher = matcher();
+        assert_eq!(matcher.key("ten"), "epidermal necrolysis toxic");
+        assert_eq!(matcher.key("grade ten"), "grade ten");
+        assert_eq!(matcher.key("pe"), "embolism pulmonary");
+        assert_eq!(matcher.key("pe tube"), "pe tube");
+    }
+}
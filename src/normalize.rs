@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use config::Config;
+use deadpool::managed::Object;
+use deadpool_postgres::tokio_postgres::types::{FromSql, ToSql};
+use deadpool_postgres::Manager;
+use log::info;
+
+use crate::db::{execute_params, query};
+
+/// Rules used for drug names: interventions, group titles and descriptions and RxNorm strings
+pub const DRUG_RULES: [&str; 9] = [
+    "lowercase",
+    "remove_non_alpha_numeric",
+    "remove_q2w",
+    "remove_word:qd",
+    "remove_word:group",
+    "remove_cohort",
+    "remove_word:arm",
+    "remove_spaces",
+    "trim",
+];
+
+/// Rules used for adverse event terms and MedDRA preferred and lowest level terms
+pub const MEDDRA_RULES: [&str; 8] = [
+    "lowercase",
+    "remove_non_alpha",
+    "remove_word:other",
+    "remove_word:nos",
+    "remove_word:any",
+    "remove_word:specify",
+    "remove_spaces",
+    "trim",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// Lowercases the whole string
+    Lowercase,
+    /// Replaces everything that is not a-z or 0-9 with a space
+    NonAlphaNumeric,
+    /// Replaces everything that is not a-z with a space
+    NonAlpha,
+    /// Removes dosing intervals such as q2w or q3w
+    DosingInterval,
+    /// Removes the word cohort together with the number following it
+    Cohort,
+    /// Removes every occurrence of a single word
+    Word(String),
+    /// Collapses consecutive spaces into one
+    Spaces,
+    /// Removes leading and trailing whitespace
+    Trim,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(word) = s.strip_prefix("remove_word:") {
+            if word.is_empty() || word.contains(' ') {
+                return Err(format!("{:?} must name exactly one word to remove", s));
+            }
+            return Ok(Rule::Word(word.to_lowercase()));
+        }
+        match s {
+            "lowercase" => Ok(Rule::Lowercase),
+            "remove_non_alpha_numeric" => Ok(Rule::NonAlphaNumeric),
+            "remove_non_alpha" => Ok(Rule::NonAlpha),
+            "remove_q2w" => Ok(Rule::DosingInterval),
+            "remove_cohort" => Ok(Rule::Cohort),
+            "remove_spaces" => Ok(Rule::Spaces),
+            "trim" => Ok(Rule::Trim),
+            _ => Err(format!("{:?} is not a known normalization rule", s)),
+        }
+    }
+}
+
+impl Rule {
+    fn apply(&self, text: String) -> String {
+        match self {
+            Rule::Lowercase => text.to_lowercase(),
+            Rule::NonAlphaNumeric => text
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_lowercase() || c.is_ascii_digit() {
+                        c
+                    } else {
+                        ' '
+                    }
+                })
+                .collect(),
+            Rule::NonAlpha => text
+                .chars()
+                .map(|c| if c.is_ascii_lowercase() { c } else { ' ' })
+                .collect(),
+            Rule::DosingInterval => remove_words(&text, |w, _| is_dosing_interval(w)),
+            Rule::Cohort => remove_words(&text, |w, previous| {
+                w == "cohort" || (previous == Some("cohort") && starts_with_digit(w))
+            }),
+            Rule::Word(word) => remove_words(&text, |w, _| w == word),
+            Rule::Spaces => {
+                let mut collapsed = String::with_capacity(text.len());
+                for c in text.chars() {
+                    if !(c == ' ' && collapsed.ends_with(' ')) {
+                        collapsed.push(c);
+                    }
+                }
+                collapsed
+            }
+            Rule::Trim => text.trim().to_string(),
+        }
+    }
+}
+
+/// Removes the space separated words for which `remove` returns true, `remove` also receives the
+/// word preceding the current one. Spacing around the remaining words is left untouched.
+fn remove_words<F>(text: &str, remove: F) -> String
+where
+    F: Fn(&str, Option<&str>) -> bool,
+{
+    let mut previous: Option<&str> = None;
+    let mut kept: Vec<&str> = Vec::new();
+    for word in text.split(' ') {
+        if !remove(word, previous) {
+            kept.push(word);
+        }
+        if !word.is_empty() {
+            previous = Some(word);
+        }
+    }
+    kept.join(" ")
+}
+
+fn is_dosing_interval(word: &str) -> bool {
+    word.len() > 2
+        && word.starts_with('q')
+        && word.ends_with('w')
+        && word[1..word.len() - 1].chars().all(|c| c.is_ascii_digit())
+}
+
+fn starts_with_digit(word: &str) -> bool {
+    word.chars().next().is_some_and(|c| c.is_ascii_digit())
+}
+
+/// An ordered list of normalization rules. The same normalizer must be applied to both sides of
+/// a comparison, e.g. interventions and RxNorm strings or adverse event terms and MedDRA terms.
+#[derive(Debug, Clone)]
+pub struct Normalizer {
+    rules: Vec<Rule>,
+}
+
+impl Normalizer {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self { rules }
+    }
+
+    /// Reads the rule list under `key` from the settings, falling back to `default` when the key
+    /// is absent.
+    pub fn from_settings(settings: &Config, key: &str, default: &[&str]) -> Self {
+        let names: Vec<String> = match settings.get_array(key) {
+            Ok(values) => values
+                .into_iter()
+                .map(|v| {
+                    v.into_string()
+                        .unwrap_or_else(|e| panic!("Invalid value in {}: {}", key, e))
+                })
+                .collect(),
+            Err(_) => default.iter().map(|r| r.to_string()).collect(),
+        };
+        let rules = names
+            .iter()
+            .map(|n| {
+                n.parse()
+                    .unwrap_or_else(|e| panic!("Invalid {}: {}", key, e))
+            })
+            .collect();
+        Self::new(rules)
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        self.rules
+            .iter()
+            .fold(text.to_string(), |acc, rule| rule.apply(acc))
+    }
+}
+
+/// Normalizes a text column in the database. The select query must return the columns `id` and
+/// `term`, the update query receives an array of ids ($1) and an array of normalized terms ($2).
+pub async fn normalize_column<T>(
+    select_query: &str,
+    update_query: &str,
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    normalizer: &Normalizer,
+) where
+    T: for<'a> FromSql<'a> + ToSql + Sync,
+{
+    let rows = query(select_query, client, queries, &[]).await;
+    let mut ids: Vec<T> = Vec::with_capacity(rows.len());
+    let mut terms: Vec<String> = Vec::with_capacity(rows.len());
+    for row in rows {
+        let term: String = row.get("term");
+        ids.push(row.get("id"));
+        terms.push(normalizer.normalize(&term));
+    }
+    info!("Normalized {} terms using {}", terms.len(), select_query);
+    execute_params(update_query, client, queries, &[&ids, &terms]).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(rules: &[&str]) -> Normalizer {
+        Normalizer::new(rules.iter().map(|r| r.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn drug_rules_clean_interventions() {
+        let n = normalizer(&DRUG_RULES);
+        assert_eq!(
+            n.normalize("Pembrolizumab 200mg Q3W"),
+            "pembrolizumab 200mg"
+        );
+        assert_eq!(n.normalize("Arm A: Metformin QD"), "a metformin");
+        assert_eq!(n.normalize("Cohort 2 - Nivolumab"), "nivolumab");
+        assert_eq!(n.normalize("Placebo Group"), "placebo");
+        assert_eq!(n.normalize("  5-Fluorouracil  "), "5 fluorouracil");
+    }
+
+    #[test]
+    fn drug_rules_only_remove_whole_words() {
+        let n = normalizer(&DRUG_RULES);
+        assert_eq!(n.normalize("Armodafinil"), "armodafinil");
+        assert_eq!(n.normalize("cohortin"), "cohortin");
+        assert_eq!(n.normalize("cohort a"), "a");
+    }
+
+    #[test]
+    fn meddra_rules_clean_adverse_events() {
+        let n = normalizer(&MEDDRA_RULES);
+        assert_eq!(n.normalize("Rash, NOS"), "rash");
+        assert_eq!(n.normalize("Infection - other, specify"), "infection");
+        assert_eq!(n.normalize("Hepatitis B"), "hepatitis b");
+        assert_eq!(n.normalize("Mother's anxiety"), "mother s anxiety");
+        assert_eq!(n.normalize("COVID-19"), "covid");
+    }
+
+    #[test]
+    fn normalization_is_idempotent() {
+        for rules in [&DRUG_RULES[..], &MEDDRA_RULES[..]] {
+            let n = normalizer(rules);
+            for text in ["R-CHOP q2w Group 1", "Nausea/vomiting (other)", "  "] {
+                let once = n.normalize(text);
+                assert_eq!(n.normalize(&once), once);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_rules_are_rejected() {
+        assert!("remove_everything".parse::<Rule>().is_err());
+        assert!("remove_word:".parse::<Rule>().is_err());
+        assert_eq!(
+            "remove_word:Arm".parse::<Rule>(),
+            Ok(Rule::Word(String::from("arm")))
+        );
+    }
+}
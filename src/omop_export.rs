@@ -0,0 +1,40 @@
+use std::error::Error;
+
+use deadpool_postgres::Pool;
+use log::info;
+use rawsql::Loader;
+
+use crate::db::{execute, query};
+use crate::run_log::RunLog;
+
+/// Writes the results of earlier runs as OMOP CDM style tables in the ctgov_omop schema: the arms,
+/// their drug exposures and their adverse event counts, with concept ids from the OMOP vocabulary
+/// in the omop schema.
+pub async fn export(pool: &Pool, run_log: &RunLog) -> Result<(), Box<dyn Error>> {
+    info!("Exporting to the OMOP CDM tables in ctgov_omop");
+    let client = pool.get().await?;
+    let queries = Loader::read_queries_from("./sql/omop_export.sql").unwrap();
+
+    execute("create_schema", &client, &queries).await;
+    for table in ["trial_arm", "drug_exposure", "adverse_event"] {
+        info!("Writing ctgov_omop.{}", table);
+        execute(&format!("drop_{}", table), &client, &queries).await;
+        execute(&format!("create_{}", table), &client, &queries).await;
+    }
+
+    let counts = &query("count_rows", &client, &queries, &[]).await[0];
+    let count = |column: &str| -> i64 { counts.get(column) };
+    info!("Exported {} arms", count("trial_arms"));
+    info!(
+        "Exported {} drug exposures, {} without a standard concept",
+        count("drug_exposures"),
+        count("unknown_drugs")
+    );
+    info!(
+        "Exported {} adverse events, {} without a SNOMED CT concept",
+        count("adverse_events"),
+        count("unknown_conditions")
+    );
+    run_log.record(pool, "omop_export", "ctgov_omop").await;
+    Ok(())
+}
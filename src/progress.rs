@@ -0,0 +1,49 @@
+use std::io::Stdout;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use pbr::ProgressBar;
+
+/// A progress bar over the real amount of work of a stage, it can be shared between threads. The
+/// bar is redrawn about a hundred times, however large the stage is.
+pub struct Progress {
+    bar: Mutex<ProgressBar<Stdout>>,
+    done: AtomicU64,
+    total: u64,
+    step: u64,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        let total = total as u64;
+        let mut bar = ProgressBar::new(total);
+        bar.set_width(Some(80));
+        bar.show_speed = false;
+        Self {
+            bar: Mutex::new(bar),
+            done: AtomicU64::new(0),
+            total,
+            step: (total / 100).max(1),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: usize) {
+        let n = n as u64;
+        let before = self.done.fetch_add(n, Ordering::Relaxed);
+        let done = before + n;
+        if done / self.step != before / self.step || done >= self.total {
+            self.bar.lock().unwrap().set(done.min(self.total));
+        }
+    }
+
+    pub fn finish(self) {
+        let mut bar = self.bar.into_inner().unwrap();
+        bar.set(self.done.into_inner().min(self.total));
+        bar.finish();
+        println!();
+    }
+}
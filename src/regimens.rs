@@ -0,0 +1,87 @@
+use std::fs;
+
+use log::info;
+
+use crate::normalize::Normalizer;
+
+/// A treatment regimen acronym such as FOLFOX together with the ingredients it consists of
+#[derive(Debug, Clone)]
+pub struct Regimen {
+    /// The acronym as written in the regimen file
+    pub name: String,
+    /// The normalized acronym, as it appears in normalized interventions and group titles
+    pub acronym: String,
+    /// The normalized names of the ingredients
+    pub ingredients: Vec<String>,
+}
+
+impl Regimen {
+    /// Describes the expansion, stored as match_detail in ctgov.drug_mapping_rxcui
+    pub fn detail(&self) -> String {
+        format!("{}: {}", self.name, self.ingredients.join(" + "))
+    }
+}
+
+/// Reads a tab separated file with a header and the columns regimen and ingredients, the
+/// ingredients are separated by commas. Longer acronyms come first so that e.g. R-CHOP is
+/// expanded before CHOP.
+pub fn read_regimens(path: &str, normalizer: &Normalizer) -> Vec<Regimen> {
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Error reading regimens file {}: {}", path, e));
+    let regimens = parse_regimens(&content, path, normalizer);
+    info!("Read {} regimens from {}", regimens.len(), path);
+    regimens
+}
+
+fn parse_regimens(content: &str, path: &str, normalizer: &Normalizer) -> Vec<Regimen> {
+    let mut regimens: Vec<Regimen> = content
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (name, ingredients) = line
+                .split_once('\t')
+                .unwrap_or_else(|| panic!("Invalid line in {}: {:?}", path, line));
+            Regimen {
+                name: name.trim().to_string(),
+                acronym: normalizer.normalize(name),
+                ingredients: ingredients
+                    .split(',')
+                    .map(|i| normalizer.normalize(i))
+                    .filter(|i| !i.is_empty())
+                    .collect(),
+            }
+        })
+        .filter(|r| !r.acronym.is_empty())
+        .collect();
+    regimens.sort_by_key(|r| std::cmp::Reverse(r.acronym.len()));
+    regimens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::DRUG_RULES;
+
+    #[test]
+    fn parses_and_normalizes_regimens() {
+        let normalizer = Normalizer::new(DRUG_RULES.iter().map(|r| r.parse().unwrap()).collect());
+        let content = "regimen\tingredients\n\
+            CHOP\tCyclophosphamide, Doxorubicin, Vincristine, Prednisone\n\
+            \n\
+            R-CHOP\tRituximab, Cyclophosphamide, Doxorubicin, Vincristine, Prednisone\n\
+            FOLFOX\tLeucovorin, 5-Fluorouracil,, Oxaliplatin\n";
+        let regimens = parse_regimens(content, "regimens.tsv", &normalizer);
+        let acronyms: Vec<&str> = regimens.iter().map(|r| r.acronym.as_str()).collect();
+        assert_eq!(acronyms, vec!["r chop", "folfox", "chop"]);
+        assert_eq!(regimens[0].name, "R-CHOP");
+        assert_eq!(
+            regimens[1].ingredients,
+            vec!["leucovorin", "5 fluorouracil", "oxaliplatin"]
+        );
+        assert_eq!(
+            regimens[1].detail(),
+            "FOLFOX: leucovorin + 5 fluorouracil + oxaliplatin"
+        );
+    }
+}
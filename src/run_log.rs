@@ -0,0 +1,49 @@
+use chrono::Local;
+use deadpool_postgres::Pool;
+use log::info;
+use rawsql::Loader;
+
+use crate::db::{execute, execute_params, query};
+
+/// Records what a run of Dracula used, e.g. the versions of the lists and vocabularies, in
+/// ctgov.dracula_run_log. Every run is identified by the moment it started.
+pub struct RunLog {
+    run_id: String,
+    queries: Loader,
+}
+
+impl RunLog {
+    pub async fn start(pool: &Pool) -> Self {
+        let queries = Loader::read_queries_from("./sql/run_log.sql").unwrap();
+        let client = pool.get().await.unwrap();
+        execute("create_run_log", &client, &queries).await;
+        let run_id = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        info!("Logging this run as {}", run_id);
+        Self { run_id, queries }
+    }
+
+    pub async fn record(&self, pool: &Pool, key: &str, value: &str) {
+        info!("Using {} {}", key, value);
+        let client = pool.get().await.unwrap();
+        execute_params(
+            "insert_run_log",
+            &client,
+            &self.queries,
+            &[&self.run_id, &key, &value],
+        )
+        .await;
+    }
+
+    /// The value recorded for the key by the most recent earlier run
+    pub async fn previous(&self, pool: &Pool, key: &str) -> Option<String> {
+        let client = pool.get().await.unwrap();
+        let rows = query(
+            "find_previous_value",
+            &client,
+            &self.queries,
+            &[&key, &self.run_id],
+        )
+        .await;
+        rows.first().map(|row| row.get("value"))
+    }
+}
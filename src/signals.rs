@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+use deadpool::managed::Object;
+use deadpool_postgres::{Manager, Pool};
+use log::info;
+use rawsql::Loader;
+
+use crate::db::{execute, execute_params, query};
+
+/// The normal quantile for 95% confidence intervals
+const Z: f64 = 1.959964;
+/// Added to every cell of a trial with a zero cell before computing risk ratios and the variance
+/// of risk differences
+const CONTINUITY_CORRECTION: f64 = 0.5;
+
+/// An ingredient and preferred term: in_rxcui, in_str, pt_code and pt_name
+type Pair = (String, String, i32, String);
+/// The placebo controlled trials of every pair, by NCT id
+type Trials = BTreeMap<Pair, Vec<(String, Stratum)>>;
+
+/// The counts of one trial: a out of n1 treated and c out of n0 control subjects were affected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stratum {
+    pub a: f64,
+    pub n1: f64,
+    pub c: f64,
+    pub n0: f64,
+}
+
+/// An estimate with its 95% confidence interval
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Estimate {
+    fn new(value: f64, se: f64) -> Self {
+        Self {
+            value,
+            lower: value - Z * se,
+            upper: value + Z * se,
+        }
+    }
+
+    /// An estimate computed on the log scale, transformed back
+    fn exp(log_value: f64, se: f64) -> Self {
+        let log = Self::new(log_value, se);
+        Self {
+            value: log.value.exp(),
+            lower: log.lower.exp(),
+            upper: log.upper.exp(),
+        }
+    }
+}
+
+impl Stratum {
+    /// The affected subjects are capped at the subjects at risk, more would give no intervals
+    pub fn new(a: i64, n1: i64, c: i64, n0: i64) -> Self {
+        Self {
+            a: a.min(n1) as f64,
+            n1: n1 as f64,
+            c: c.min(n0) as f64,
+            n0: n0 as f64,
+        }
+    }
+
+    /// The stratum used for risk ratios: trials without events in either arm carry no information
+    /// and give None, trials with a zero cell get the continuity correction
+    fn corrected(&self) -> Option<Self> {
+        if self.a == 0.0 && self.c == 0.0 {
+            return None;
+        }
+        Some(self.with_correction())
+    }
+
+    /// The stratum with the continuity correction when it has a zero cell
+    fn with_correction(&self) -> Self {
+        let (b, d) = (self.n1 - self.a, self.n0 - self.c);
+        if self.a == 0.0 || self.c == 0.0 || b == 0.0 || d == 0.0 {
+            Self {
+                a: self.a + CONTINUITY_CORRECTION,
+                n1: self.n1 + 2.0 * CONTINUITY_CORRECTION,
+                c: self.c + CONTINUITY_CORRECTION,
+                n0: self.n0 + 2.0 * CONTINUITY_CORRECTION,
+            }
+        } else {
+            *self
+        }
+    }
+
+    /// The difference is taken from the counts, its variance from the corrected counts as a zero
+    /// cell in both arms, e.g. no events at all, would otherwise give a zero-width interval
+    pub fn risk_difference(&self) -> Estimate {
+        let s = self.with_correction();
+        let (b, d) = (s.n1 - s.a, s.n0 - s.c);
+        let variance = s.a * b / s.n1.powi(3) + s.c * d / s.n0.powi(3);
+        Estimate::new(self.a / self.n1 - self.c / self.n0, variance.sqrt())
+    }
+
+    pub fn risk_ratio(&self) -> Option<Estimate> {
+        let s = self.corrected()?;
+        let variance = 1.0 / s.a - 1.0 / s.n1 + 1.0 / s.c - 1.0 / s.n0;
+        Some(Estimate::exp(
+            ((s.a / s.n1) / (s.c / s.n0)).ln(),
+            variance.sqrt(),
+        ))
+    }
+}
+
+/// The Mantel-Haenszel risk difference over the trials with the Greenland-Robins variance
+pub fn mantel_haenszel_rd(strata: &[Stratum]) -> Option<Estimate> {
+    let (mut numerator, mut weights, mut variance) = (0.0, 0.0, 0.0);
+    for s in strata {
+        let (b, d, n) = (s.n1 - s.a, s.n0 - s.c, s.n1 + s.n0);
+        numerator += (s.a * s.n0 - s.c * s.n1) / n;
+        weights += s.n1 * s.n0 / n;
+        variance += (s.a * b * s.n0.powi(3) + s.c * d * s.n1.powi(3)) / (s.n1 * s.n0 * n * n);
+    }
+    if weights == 0.0 {
+        return None;
+    }
+    Some(Estimate::new(
+        numerator / weights,
+        (variance / (weights * weights)).sqrt(),
+    ))
+}
+
+/// The Mantel-Haenszel risk ratio over the trials with the Greenland-Robins variance, trials with
+/// a zero cell get the continuity correction and trials without events are left out
+pub fn mantel_haenszel_rr(strata: &[Stratum]) -> Option<Estimate> {
+    let (mut r, mut s, mut p) = (0.0, 0.0, 0.0);
+    for stratum in strata.iter().filter_map(|s| s.corrected()) {
+        let Stratum { a, n1, c, n0 } = stratum;
+        let n = n1 + n0;
+        r += a * n0 / n;
+        s += c * n1 / n;
+        p += (n1 * n0 * (a + c) - a * c * n) / (n * n);
+    }
+    if r == 0.0 || s == 0.0 {
+        return None;
+    }
+    Some(Estimate::exp((r / s).ln(), (p / (r * s)).sqrt()))
+}
+
+/// Compares every arm given an ingredient with the placebo arms of the same trial, per preferred
+/// term, in ctgov.ingredient_event_contrasts and pools the trials per ingredient and preferred
+/// term in ctgov.ingredient_event_signals
+pub async fn compute(pool: &Pool) -> Result<(), Box<dyn Error>> {
+    info!("Comparing the ingredient arms with the placebo arms of their trials");
+    let client = pool.get().await?;
+    let queries = Loader::read_queries_from("./sql/signals.sql").unwrap();
+
+    let mut pairs: Trials = BTreeMap::new();
+    for row in query("find_strata", &client, &queries, &[]).await {
+        let stratum = Stratum::new(
+            row.get("treated_affected"),
+            row.get("treated_at_risk"),
+            row.get("control_affected"),
+            row.get("control_at_risk"),
+        );
+        pairs
+            .entry((
+                row.get("in_rxcui"),
+                row.get("in_str"),
+                row.get("pt_code"),
+                row.get("pt_name"),
+            ))
+            .or_default()
+            .push((row.get("nct_id"), stratum));
+    }
+    info!(
+        "Found placebo controlled trials for {} ingredient and preferred term pairs",
+        pairs.len()
+    );
+
+    execute("drop_contrast_table", &client, &queries).await;
+    execute("create_contrast_table", &client, &queries).await;
+    execute("drop_signal_table", &client, &queries).await;
+    execute("create_signal_table", &client, &queries).await;
+    let contrasts: Vec<(&Pair, &str, Stratum)> = pairs
+        .iter()
+        .flat_map(|(pair, trials)| {
+            trials
+                .iter()
+                .map(move |(nct_id, s)| (pair, nct_id.as_str(), *s))
+        })
+        .collect();
+    insert_contrasts(&client, &queries, &contrasts).await;
+    insert_signals(&client, &queries, &pairs).await;
+    Ok(())
+}
+
+async fn insert_contrasts(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    contrasts: &[(&Pair, &str, Stratum)],
+) {
+    let rd: Vec<Estimate> = contrasts.iter().map(|c| c.2.risk_difference()).collect();
+    let rr: Vec<Option<Estimate>> = contrasts.iter().map(|c| c.2.risk_ratio()).collect();
+    let nct_ids: Vec<&str> = contrasts.iter().map(|c| c.1).collect();
+    let in_rxcuis: Vec<&str> = contrasts.iter().map(|c| c.0 .0.as_str()).collect();
+    let pt_codes: Vec<i32> = contrasts.iter().map(|c| c.0 .2).collect();
+    let counts = counts(contrasts.iter().map(|c| c.2));
+    execute_params(
+        "insert_contrasts",
+        client,
+        queries,
+        &[
+            &nct_ids,
+            &in_rxcuis,
+            &pt_codes,
+            &counts.0,
+            &counts.1,
+            &counts.2,
+            &counts.3,
+            &rd.iter().map(|e| e.value).collect::<Vec<f64>>(),
+            &rd.iter().map(|e| e.lower).collect::<Vec<f64>>(),
+            &rd.iter().map(|e| e.upper).collect::<Vec<f64>>(),
+            &rr.iter()
+                .map(|e| e.map(|e| e.value))
+                .collect::<Vec<Option<f64>>>(),
+            &rr.iter()
+                .map(|e| e.map(|e| e.lower))
+                .collect::<Vec<Option<f64>>>(),
+            &rr.iter()
+                .map(|e| e.map(|e| e.upper))
+                .collect::<Vec<Option<f64>>>(),
+        ],
+    )
+    .await;
+    info!("Stored {} trial contrasts", contrasts.len());
+}
+
+async fn insert_signals(
+    client: &Object<Manager>,
+    queries: &HashMap<String, String>,
+    pairs: &Trials,
+) {
+    let strata: Vec<Vec<Stratum>> = pairs
+        .values()
+        .map(|trials| trials.iter().map(|t| t.1).collect())
+        .collect();
+    let rd: Vec<Option<Estimate>> = strata.iter().map(|s| mantel_haenszel_rd(s)).collect();
+    let rr: Vec<Option<Estimate>> = strata.iter().map(|s| mantel_haenszel_rr(s)).collect();
+    let totals = counts(strata.iter().map(|trials| {
+        trials
+            .iter()
+            .fold(Stratum::new(0, 0, 0, 0), |t, s| Stratum {
+                a: t.a + s.a,
+                n1: t.n1 + s.n1,
+                c: t.c + s.c,
+                n0: t.n0 + s.n0,
+            })
+    }));
+    execute_params(
+        "insert_signals",
+        client,
+        queries,
+        &[
+            &pairs.keys().map(|k| k.0.as_str()).collect::<Vec<&str>>(),
+            &pairs.keys().map(|k| k.1.as_str()).collect::<Vec<&str>>(),
+            &pairs.keys().map(|k| k.2).collect::<Vec<i32>>(),
+            &pairs.keys().map(|k| k.3.as_str()).collect::<Vec<&str>>(),
+            &strata.iter().map(|s| s.len() as i32).collect::<Vec<i32>>(),
+            &totals.0,
+            &totals.1,
+            &totals.2,
+            &totals.3,
+            &rd.iter()
+                .map(|e| e.map(|e| e.value))
+                .collect::<Vec<Option<f64>>>(),
+            &rd.iter()
+                .map(|e| e.map(|e| e.lower))
+                .collect::<Vec<Option<f64>>>(),
+            &rd.iter()
+                .map(|e| e.map(|e| e.upper))
+                .collect::<Vec<Option<f64>>>(),
+            &rr.iter()
+                .map(|e| e.map(|e| e.value))
+                .collect::<Vec<Option<f64>>>(),
+            &rr.iter()
+                .map(|e| e.map(|e| e.lower))
+                .collect::<Vec<Option<f64>>>(),
+            &rr.iter()
+                .map(|e| e.map(|e| e.upper))
+                .collect::<Vec<Option<f64>>>(),
+        ],
+    )
+    .await;
+    info!("Stored {} pooled signals", pairs.len());
+}
+
+/// The four counts of the strata as separate columns
+fn counts(strata: impl Iterator<Item = Stratum>) -> (Vec<i64>, Vec<i64>, Vec<i64>, Vec<i64>) {
+    let mut columns = (vec![], vec![], vec![], vec![]);
+    for s in strata {
+        columns.0.push(s.a as i64);
+        columns.1.push(s.n1 as i64);
+        columns.2.push(s.c as i64);
+        columns.3.push(s.n0 as i64);
+    }
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn single_trial_pools_to_itself() {
+        let s = Stratum::new(10, 100, 5, 100);
+        let rr = s.risk_ratio().unwrap();
+        let mh_rr = mantel_haenszel_rr(&[s]).unwrap();
+        assert!(close(rr.value, 2.0));
+        assert!(close(mh_rr.value, rr.value));
+        assert!(close(mh_rr.lower, rr.lower));
+        let rd = s.risk_difference();
+        let mh_rd = mantel_haenszel_rd(&[s]).unwrap();
+        assert!(close(rd.value, 0.05));
+        assert!(close(mh_rd.value, rd.value));
+        assert!(close(mh_rd.upper, rd.upper));
+    }
+
+    #[test]
+    fn pools_trials() {
+        let strata = [Stratum::new(2, 10, 1, 10), Stratum::new(4, 20, 2, 20)];
+        assert!(close(mantel_haenszel_rr(&strata).unwrap().value, 2.0));
+        assert!(close(mantel_haenszel_rd(&strata).unwrap().value, 0.1));
+    }
+
+    #[test]
+    fn corrects_zero_cells() {
+        let s = Stratum::new(0, 10, 3, 10);
+        assert!(close(s.risk_ratio().unwrap().value, 0.5 / 3.5));
+        let none = Stratum::new(0, 10, 0, 10);
+        assert_eq!(none.risk_ratio(), None);
+        assert_eq!(mantel_haenszel_rr(&[none]), None);
+        assert!(close(mantel_haenszel_rd(&[none]).unwrap().value, 0.0));
+    }
+
+    #[test]
+    fn widens_risk_differences_with_zero_cells() {
+        for s in [Stratum::new(0, 10, 0, 20), Stratum::new(10, 10, 20, 20)] {
+            let rd = s.risk_difference();
+            assert!(close(rd.value, 0.0));
+            assert!(rd.lower < 0.0 && rd.upper > 0.0);
+        }
+        let s = Stratum::new(2, 10, 1, 10);
+        let rd = s.risk_difference();
+        assert!(close(
+            rd.upper - rd.value,
+            Z * (0.16f64 / 10.0 + 0.09 / 10.0).sqrt()
+        ));
+    }
+
+    #[test]
+    fn caps_affected_at_risk() {
+        let s = Stratum::new(12, 10, 3, 10);
+        assert_eq!(s, Stratum::new(10, 10, 3, 10));
+        let rd = s.risk_difference();
+        assert!(rd.lower.is_finite() && rd.upper.is_finite());
+        let rr = mantel_haenszel_rr(&[s]).unwrap();
+        assert!(rr.lower.is_finite() && rr.upper.is_finite());
+    }
+}
@@ -0,0 +1,41 @@
+use std::error::Error;
+
+use deadpool_postgres::Pool;
+use log::info;
+use rawsql::Loader;
+
+use crate::db::{execute, query};
+use crate::run_log::RunLog;
+
+/// Maps the preferred terms of the reported events to SNOMED CT concepts along the OMOP
+/// 'MedDRA - SNOMED eq' relationships, linking every event to its concepts in
+/// ctgov.reported_event_snomed.
+pub async fn map_to_snomed(pool: &Pool, run_log: &RunLog) -> Result<(), Box<dyn Error>> {
+    info!("Mapping the preferred terms to SNOMED CT");
+    let client = pool.get().await?;
+    let queries = Loader::read_queries_from("./sql/snomed_mapping.sql").unwrap();
+
+    let versions = query("find_vocabulary_versions", &client, &queries, &[]).await;
+    for row in versions {
+        let vocabulary: &str = row.get("vocabulary_id");
+        let version: &str = row.get("vocabulary_version");
+        run_log
+            .record(pool, &format!("omop_{}_version", vocabulary), version)
+            .await;
+    }
+
+    execute("drop_snomed_map", &client, &queries).await;
+    execute("create_snomed_map", &client, &queries).await;
+    execute("drop_event_snomed_table", &client, &queries).await;
+    execute("create_event_snomed_table", &client, &queries).await;
+    execute("event_snomed_index", &client, &queries).await;
+
+    let counts = query("count_snomed_events", &client, &queries, &[]).await;
+    let mapped: i64 = counts[0].get("mapped");
+    let total: i64 = counts[0].get("total");
+    info!(
+        "Linked {} out of {} mapped events to SNOMED CT",
+        mapped, total
+    );
+    Ok(())
+}